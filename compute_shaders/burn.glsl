@@ -0,0 +1,46 @@
+#version 450
+
+#include "includes.glsl"
+
+// Burning matter burns down its lifetime & leaves `burns_into` behind
+Matter burn_down(Matter m) {
+    MatterDefinition definition = matter_definitions[m.matter];
+    if (definition.lifetime == 0) {
+        return m;
+    }
+    uint lifetime = get_lifetime(m);
+    if (lifetime > 1) {
+        return set_lifetime(m, lifetime - 1);
+    }
+    return with_initial_state(definition.burns_into);
+}
+
+// Flammable matter catches fire from each burning neighbor with its flammability as the chance
+void burn(ivec2 pos) {
+    Matter current = read_matter(pos);
+    Matter m = current;
+    float flammability = matter_definitions[current.matter].flammability;
+    if (is_burning(current)) {
+        m = burn_down(current);
+    } else if (flammability > 0.0) {
+        for (int dir = 0; dir < 8; dir++) {
+            Matter neighbor = get_neighbor(pos, dir);
+            if (is_burning(neighbor)) {
+                mark_changed(pos);
+                if (random(pos, uint(BURN_SALT + dir)) < flammability) {
+                    m = set_lifetime(neighbor, matter_definitions[neighbor.matter].lifetime);
+                    break;
+                }
+            }
+        }
+    }
+    write_matter(pos, m);
+}
+
+void main() {
+    ivec2 pos = get_current_sim_pos();
+    if (!is_inside_sim_canvas(pos) || is_tile_asleep()) {
+        return;
+    }
+    burn(pos);
+}
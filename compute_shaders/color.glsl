@@ -23,5 +23,9 @@ void write_color_to_image(ivec2 pos) {
 }
 
 void main() {
-    write_color_to_image(get_current_sim_pos());
+    ivec2 pos = get_current_sim_pos();
+    if (!is_inside_sim_canvas(pos)) {
+        return;
+    }
+    write_color_to_image(pos);
 }
\ No newline at end of file
@@ -0,0 +1,37 @@
+#version 450
+
+#include "includes.glsl"
+
+// Liquid spreads & gas drifts sideways on empty by one cell per pass, in the first `dispersion` passes of a
+// move step. Each cell can only receive from one direction per pass, so no two cells claim the same target
+void disperse_empty(ivec2 pos, int from_dir, int to_dir) {
+    Matter current = read_matter(pos);
+    ivec2 from_pos = get_pos_at_dir(pos, from_dir);
+    ivec2 to_pos = get_pos_at_dir(pos, to_dir);
+    Matter from = get_neighbor(pos, from_dir);
+    Matter to = get_neighbor(pos, to_dir);
+
+    Matter m = current;
+    if (is_inside_sim_canvas(from_pos) &&
+        (disperses_on_empty(from, current, from_pos) || drifts_on_empty(from, current, from_pos))) {
+        m = from;
+    } else if (is_inside_sim_canvas(to_pos) &&
+        (disperses_on_empty(current, to, pos) || drifts_on_empty(current, to, pos))) {
+        m = to;
+    } else if (can_drift_on_empty(from, current) || can_drift_on_empty(current, to)) {
+        mark_changed(pos);
+    }
+    write_matter(pos, m);
+}
+
+void main() {
+    ivec2 pos = get_current_sim_pos();
+    if (!is_inside_sim_canvas(pos) || is_tile_asleep()) {
+        return;
+    }
+    if ((sim_step() + move_step()) % 2 == 0) {
+        disperse_empty(pos, RIGHT, LEFT);
+    } else {
+        disperse_empty(pos, LEFT, RIGHT);
+    }
+}
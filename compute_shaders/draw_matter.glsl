@@ -2,24 +2,17 @@
 
 #include "includes.glsl"
 
-// https://stackoverflow.com/questions/4200224/random-noise-functions-for-glsl
-float PHI = 1.61803398874989484820459; // Golden ratio
-float rand(in vec2 xy, in float seed){
-    return fract(tan(distance(xy * PHI, xy) * seed) * xy.x);
-}
-
-vec4 vary_color_rgb(vec4 color, ivec2 seed_pos) {
-    // Just use the same seed (means same color for individual xy position)
-    float seed = 0.1;
-    float p = rand(seed_pos, seed);
-    float variation = -0.1 + 0.2 * p;
+vec4 vary_color_rgb(vec4 color, ivec2 seed_pos, float color_variation) {
+    float p = random(seed_pos, COLOR_SALT);
+    float variation = -color_variation + 2.0 * color_variation * p;
     color.rgb += vec3(variation);
     return color;
 }
 
-uint variate_color(ivec2 pos, uint color) {
+uint variate_color(ivec2 pos, uint color, float color_variation) {
     vec4 color_f32 = matter_color_to_vec4(color);
-    vec4 variated_color_f32 = vary_color_rgb(color_f32, pos);
+    // Clamped, light colors would wrap around to dark ones
+    vec4 variated_color_f32 = clamp(vary_color_rgb(color_f32, pos, color_variation), 0.0, 1.0);
     uint rgb = ((uint(variated_color_f32.r * 255.0) & uint(255)) << uint(16)) |
             ((uint(variated_color_f32.g * 255.0) & uint(255)) << uint(8)) |
             (uint(variated_color_f32.b * 255.0) & uint(255));
@@ -35,9 +28,10 @@ void draw_matter_circle(ivec2 pos, ivec2 draw_pos, float radius, Matter matter)
         vec2 diff = vec2(pos) - vec2(draw_pos);
         float dist = length(diff);
         if (round(dist) <= radius) {
-            // We vary color only if not empty
-            if (!is_empty(matter)) {
-                matter.color = variate_color(pos, matter.color);
+            matter = set_lifetime(matter, matter_definitions[matter.matter].lifetime);
+            float color_variation = matter_definitions[matter.matter].color_variation;
+            if (color_variation > 0.0) {
+                matter.color = variate_color(pos, matter.color, color_variation);
             }
             write_matter_input(pos, matter);
         }
@@ -60,11 +54,17 @@ vec2 closest_point_on_line(vec2 v, vec2 w, vec2 p) {
 
 void main() {
     ivec2 pos = get_current_sim_pos();
-    vec2 point_on_line = closest_point_on_line(push_constants.draw_pos_start, push_constants.draw_pos_end, pos);
-    draw_matter_circle(
-        pos,
-        ivec2(point_on_line),
-        push_constants.draw_radius,
-        new_matter(push_constants.draw_matter)
-    );
+    if (!is_inside_sim_canvas(pos)) {
+        return;
+    }
+    // Later strokes paint over earlier ones. The dispatch covers all strokes, each cell skips those whose
+    // bounds don't contain it.
+    for (uint i = 0; i < push_constants.num_strokes; i++) {
+        Stroke stroke = strokes[i];
+        if (any(lessThan(pos, stroke.bounds_origin)) || any(greaterThanEqual(pos, stroke.bounds_end))) {
+            continue;
+        }
+        vec2 point_on_line = closest_point_on_line(stroke.start, stroke.end, pos);
+        draw_matter_circle(pos, ivec2(point_on_line), stroke.radius, new_matter(stroke.matter));
+    }
 }
\ No newline at end of file
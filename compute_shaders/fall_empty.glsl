@@ -2,19 +2,79 @@
 
 #include "includes.glsl"
 
+/*
+Falling matter moves up to its fall distance at once, stopping on the first cell that isn't empty. Falling
+freely speeds it up, landing stops it. A falling cell can't land on the spot another falling cell leaves,
+because it stops above that cell's current position, so no two cells land on the same spot. Being stopped by
+a falling cell only slows it down to that cell's velocity, so falling columns keep falling together.
+*/
+#define GRAVITY_ACCELERATION 4
+#define MAX_FALL_DISTANCE 8
+#define MAX_VELOCITY ((MAX_FALL_DISTANCE - 1) * VELOCITY_SCALE)
+
+int fall_distance(Matter m) {
+    return 1 + int(get_velocity(m)) / VELOCITY_SCALE;
+}
+
+bool is_fall_blocked(ivec2 pos) {
+    return !is_inside_sim_canvas(pos) || !is_empty(read_matter(pos));
+}
+
+// Velocity of falling matter stopped by the cell at blocker_pos, at most the velocity of falling matter
+uint blocked_velocity(Matter m, ivec2 blocker_pos) {
+    if (!is_inside_sim_canvas(blocker_pos)) {
+        return 0;
+    }
+    Matter blocker = read_matter(blocker_pos);
+    return is_gravity(blocker) ? min(get_velocity(m), get_velocity(blocker)) : 0;
+}
+
+// Falling matter that moved `moved` cells to pos, accelerates if nothing stopped it
+Matter fallen(Matter m, int moved, ivec2 pos) {
+    uint velocity = moved == fall_distance(m)
+        ? min(get_velocity(m) + GRAVITY_ACCELERATION, MAX_VELOCITY)
+        : blocked_velocity(m, pos + ivec2(0, -1));
+    return set_velocity(m, velocity);
+}
+
 void fall_empty(ivec2 pos) {
     Matter current = read_matter(pos);
-    Matter up = get_neighbor(pos, UP);
-    Matter down = get_neighbor(pos, DOWN);
     Matter m = current;
-    if (!is_at_border_top(pos) && falls_on_empty(up, current)) {
-        m = up;
-    } else if (!is_at_border_bottom(pos) && falls_on_empty(current, down)) {
-        m = down;
+    if (is_empty(current)) {
+        // Take the first cell above if it lands here
+        for (int moved = 1; moved <= MAX_FALL_DISTANCE; moved++) {
+            ivec2 from_pos = pos + ivec2(0, moved);
+            if (!is_inside_sim_canvas(from_pos)) {
+                break;
+            }
+            Matter from = read_matter(from_pos);
+            if (!is_empty(from)) {
+                int distance = fall_distance(from);
+                bool lands_here = distance == moved || (distance > moved && is_fall_blocked(pos + ivec2(0, -1)));
+                if (is_gravity(from) && lands_here) {
+                    m = fallen(from, moved, pos);
+                }
+                break;
+            }
+        }
+    } else if (is_gravity(current)) {
+        // Leave for the last empty cell within fall distance, swapping places with it
+        int distance = fall_distance(current);
+        int moved = 0;
+        while (moved < distance && !is_fall_blocked(pos + ivec2(0, -moved - 1))) {
+            moved++;
+        }
+        m = moved == 0
+            ? set_velocity(current, blocked_velocity(current, pos + ivec2(0, -1)))
+            : read_matter(pos + ivec2(0, -moved));
     }
     write_matter(pos, m);
 }
 
 void main() {
-    fall_empty(get_current_sim_pos());
-}
\ No newline at end of file
+    ivec2 pos = get_current_sim_pos();
+    if (!is_inside_sim_canvas(pos) || is_tile_asleep()) {
+        return;
+    }
+    fall_empty(pos);
+}
@@ -0,0 +1,58 @@
+#version 450
+
+#include "includes.glsl"
+
+/*
+Temperature belongs to positions, not to matter: moving matter leaves its heat behind & takes on the
+temperature of where it lands. Heat still spreads through whatever fills a position, by its conductivity.
+
+Temperatures close to ambient settle at it, & cells at any other temperature keep their tile awake. So the
+tiles that are asleep & the tiles around them are all at ambient temperature, heat can't flow into them &
+they are skipped like in the movement passes.
+*/
+const int HEAT_DIRS[4] = int[4](UP, RIGHT, DOWN, LEFT);
+#define AMBIENT_EPSILON 0.001
+
+// Heat flows between side neighbors by the lower conductivity of the two, so heat is only moved, never
+// created or lost, except by heat sources & settling at ambient. Then matter changes state at its threshold
+// temperatures.
+void heat(ivec2 pos) {
+    Matter current = read_matter(pos);
+    MatterDefinition definition = matter_definitions[current.matter];
+    float temperature = temperature_in[get_index(pos)];
+    float flow = 0.0;
+    for (int i = 0; i < 4; i++) {
+        ivec2 neighbor_pos = get_pos_at_dir(pos, HEAT_DIRS[i]);
+        if (is_inside_sim_canvas(neighbor_pos)) {
+            Matter neighbor = read_matter(neighbor_pos);
+            float conductivity = min(definition.conductivity, matter_definitions[neighbor.matter].conductivity);
+            flow += conductivity * (temperature_in[get_index(neighbor_pos)] - temperature);
+        }
+    }
+    temperature += 0.25 * flow;
+    if (definition.is_heat_source != 0) {
+        temperature = definition.temperature;
+    }
+    if (abs(temperature - ambient_temperature) < AMBIENT_EPSILON) {
+        temperature = ambient_temperature;
+    } else {
+        mark_changed(pos);
+    }
+
+    Matter m = current;
+    if (temperature > definition.above_temperature) {
+        m = with_initial_state(definition.above_into);
+    } else if (temperature < definition.below_temperature) {
+        m = with_initial_state(definition.below_into);
+    }
+    write_matter(pos, m);
+    temperature_out[get_index(pos)] = temperature;
+}
+
+void main() {
+    ivec2 pos = get_current_sim_pos();
+    if (!is_inside_sim_canvas(pos) || is_tile_asleep()) {
+        return;
+    }
+    heat(pos);
+}
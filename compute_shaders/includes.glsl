@@ -5,38 +5,66 @@ layout(constant_id = 0) const int canvas_size_x = 1;
 layout(constant_id = 1) const int canvas_size_y = 1;
 layout(constant_id = 2) const uint empty_matter = 1;
 layout(local_size_x_id = 3, local_size_y_id = 4, local_size_z = 1) in;
+layout(constant_id = 5) const float ambient_temperature = 20.0;
+
+#include "matter.glsl"
 
 /*
 Buffers
 */
-layout(set = 0, binding = 0) restrict buffer MatterInBuffer { uint matter_in[]; };
-layout(set = 0, binding = 1) restrict writeonly buffer MatterOutBuffer { uint matter_out[]; };
+layout(set = 0, binding = 0) restrict buffer MatterInBuffer { uvec2 matter_in[]; };
+layout(set = 0, binding = 1) restrict writeonly buffer MatterOutBuffer { uvec2 matter_out[]; };
 layout(set = 0, binding = 2, rgba8) restrict uniform writeonly image2D canvas_img;
-layout(set = 0, binding = 3) restrict writeonly buffer QueryMatterBuffer { uint query_matter[]; };
-
-layout(push_constant) uniform PushConstants {
+layout(set = 0, binding = 3) restrict readonly buffer MatterDefinitionsBuffer { MatterDefinition matter_definitions[]; };
+layout(set = 0, binding = 4) restrict readonly buffer TemperatureInBuffer { float temperature_in[]; };
+layout(set = 0, binding = 5) restrict writeonly buffer TemperatureOutBuffer { float temperature_out[]; };
+// Reactions of matter a touching matter b at a * 256 + b
+layout(set = 0, binding = 6) restrict readonly buffer ReactionsBuffer { Reaction reactions[]; };
+// Tiles whose cells changed during this step, see sleeping tiles below
+layout(set = 0, binding = 7) restrict buffer TileChangedBuffer { uint tile_changed[]; };
+// How many more steps each tile stays awake, 0 if it's asleep
+layout(set = 0, binding = 8) restrict buffer TileAwakeBuffer { uint tile_awake[]; };
+// Brush strokes queued since the last step, drawn in order
+layout(set = 0, binding = 9) restrict readonly buffer StrokesBuffer { Stroke strokes[]; };
+// Counters of the current step. Written before each submission, so recorded step command buffers can be
+// submitted again on later steps.
+layout(set = 0, binding = 10) restrict readonly buffer StepStateBuffer {
     uint sim_step;
     uint move_step;
-    vec2 draw_pos_start;
-    vec2 draw_pos_end;
-    float draw_radius;
-    uint draw_matter;
-    ivec2 query_pos;
+    uint world_seed;
+} step_state;
+
+layout(push_constant) uniform PushConstants {
+    // Movement passes since the start of the step
+    uint move_pass;
+    uint dispersion_step;
+    // Position of the first invocation, dispatches may only cover a part of the canvas
+    ivec2 dispatch_origin;
+    // Strokes in the strokes buffer to draw
+    uint num_strokes;
+    uint wake_radius;
 } push_constants;
 
+uint sim_step() {
+    return step_state.sim_step;
+}
+
+uint move_step() {
+    return step_state.move_step + push_constants.move_pass;
+}
+
 #include "dirs.glsl"
-#include "matter.glsl"
 
 /*
 Utility functions to be used in the various kernels:
 */
 
 ivec2 get_current_sim_pos() {
-    return ivec2(gl_GlobalInvocationID.xy);
+    return ivec2(gl_GlobalInvocationID.xy) + push_constants.dispatch_origin;
 }
 
 int get_index(ivec2 pos) {
-    return pos.y * canvas_size_y + pos.x;
+    return pos.y * canvas_size_x + pos.x;
 }
 
 bool is_at_border_top(ivec2 pos) {
@@ -60,24 +88,45 @@ bool is_inside_sim_canvas(ivec2 pos) {
     pos.y >= 0 && pos.y < canvas_size_y;
 }
 
-Matter read_matter(ivec2 pos) {
-    return new_matter(matter_in[get_index(pos)]);
+/*
+Sleeping tiles: The canvas is split into tiles of one work group each. Kernels mark the tiles whose cells
+change & `wake_tiles.glsl` keeps the tiles around changes awake for a few steps. Movement kernels skip tiles
+that are asleep, their cells are the same in both matter buffers.
+*/
+
+int num_tiles_x() {
+    return (canvas_size_x + int(gl_WorkGroupSize.x) - 1) / int(gl_WorkGroupSize.x);
+}
+
+int get_tile_index(ivec2 tile) {
+    return tile.y * num_tiles_x() + tile.x;
 }
 
-uint matter_to_uint(Matter matter) {
-    return ((matter.color << uint(8)) | matter.matter);
+// Keep the tile of pos awake. Also used by random rules that could have changed a cell but didn't, so their
+// tile doesn't fall asleep before they get to roll again
+void mark_changed(ivec2 pos) {
+    tile_changed[get_tile_index(pos / ivec2(gl_WorkGroupSize.xy))] = 1u;
 }
 
-void write_query_matter(Matter matter) {
-    query_matter[0] = matter_to_uint(matter);
+bool is_tile_asleep() {
+    return tile_awake[get_tile_index(ivec2(gl_WorkGroupID.xy))] == 0u;
+}
+
+Matter read_matter(ivec2 pos) {
+    return matter_from_cell(matter_in[get_index(pos)]);
 }
 
 void write_matter(ivec2 pos, Matter matter) {
-    matter_out[get_index(pos)] = matter_to_uint(matter);
+    uvec2 cell = matter_to_cell(matter);
+    if (cell != matter_in[get_index(pos)]) {
+        mark_changed(pos);
+    }
+    matter_out[get_index(pos)] = cell;
 }
 
 void write_matter_input(ivec2 pos, Matter matter) {
-    matter_in[get_index(pos)] = matter_to_uint(matter);
+    matter_in[get_index(pos)] = matter_to_cell(matter);
+    mark_changed(pos);
 }
 
 void write_image_color(ivec2 pos, vec4 color) {
@@ -104,17 +153,204 @@ bool is_empty(Matter matter) {
     return matter.matter == 0;
 }
 
-// A shortcut for Sand. Wood does not have gravity for now...
+bool has_behaviour(Matter m, uint flag) {
+    return (matter_definitions[m.matter].flags & flag) != 0;
+}
+
 bool is_gravity(Matter m) {
-    return m.matter == 1;
+    return has_behaviour(m, GRAVITY);
+}
+
+bool is_sliding(Matter m) {
+    return has_behaviour(m, SLIDES);
+}
+
+bool is_liquid(Matter m) {
+    return has_behaviour(m, LIQUID);
+}
+
+bool is_gas(Matter m) {
+    return has_behaviour(m, GAS);
+}
+
+bool is_burning(Matter m) {
+    return has_behaviour(m, BURNING);
+}
+
+bool is_static(Matter m) {
+    return has_behaviour(m, STATIC);
+}
+
+float density(Matter m) {
+    return matter_definitions[m.matter].density;
+}
+
+// Heavier matter sinks through lighter liquid or gas by swapping places with it
+bool sinks_through(Matter from, Matter to) {
+    return is_gravity(from) && (is_liquid(to) || is_gas(to)) && !is_static(to) && density(from) > density(to);
+}
+
+bool rises_on_empty(Matter from, Matter to) {
+    return is_gas(from) && is_empty(to);
+}
+
+bool slides_up_on_empty(Matter from_diagonal, Matter to_diagonal, Matter from_up) {
+    return is_gas(from_diagonal) && !is_empty(from_up) && is_empty(to_diagonal);
+}
+
+/*
+Random numbers, same as in cpu_simulator.rs. Each pass that needs random numbers uses its own salts, so
+passes don't roll the same numbers for a cell.
+*/
+#define DISPERSION_SALT 0
+#define BURN_SALT 256
+#define REACTION_SALT 512
+#define COLOR_SALT 768
+#define SLIDE_SALT 1024
+#define FRICTION_SALT 1280
+
+// PCG hash, https://www.reedbeta.com/blog/hash-functions-for-gpu-rendering/
+uint pcg(uint v) {
+    uint state = v * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Random integer for a position in the current pass, the same for the same world seed
+uint hash(ivec2 pos, uint salt) {
+    uint h = pcg(step_state.world_seed);
+    h = pcg(h ^ sim_step());
+    h = pcg(h ^ move_step());
+    h = pcg(h ^ salt);
+    h = pcg(h ^ uint(pos.x));
+    return pcg(h ^ uint(pos.y));
+}
+
+// Matter (color << 8 | matter) with the initial state of its definition, like when it turns into other matter
+Matter with_initial_state(uint matter) {
+    Matter m = new_matter(matter);
+    return set_lifetime(m, matter_definitions[m.matter].lifetime);
 }
 
-bool falls_on_empty(Matter from, Matter to) {
-    return is_gravity(from) && is_empty(to);
+// Random number in [0, 1) from `hash`
+float random(ivec2 pos, uint salt) {
+    return float(hash(pos, salt) >> 8) / 16777216.0;
+}
+
+// Random number in [0, 1) for a position that stays the same from step to step
+float position_random(ivec2 pos, uint salt) {
+    uint h = pcg(step_state.world_seed);
+    h = pcg(h ^ salt);
+    h = pcg(h ^ uint(pos.x));
+    return float(pcg(h ^ uint(pos.y)) >> 8) / 16777216.0;
+}
+
+// Friction is rolled per position rather than per step, so a cell that holds stays held & piles keep their
+// steepness instead of slowly flattening. A held cell only slides once its neighbours change, which wakes
+// its tile anyway.
+bool holds_by_friction(Matter m, ivec2 pos) {
+    return position_random(pos, FRICTION_SALT) < matter_definitions[m.matter].friction;
 }
 
 bool slides_on_empty(Matter from_diagonal, Matter to_diagonal, Matter from_down) {
-    return is_gravity(from_diagonal) && !is_empty(from_down) && is_empty(to_diagonal);
+    return is_sliding(from_diagonal) && !is_empty(from_down) && is_empty(to_diagonal);
+}
+
+bool can_drift_on_empty(Matter from, Matter to) {
+    return is_gas(from) && push_constants.dispersion_step < matter_definitions[from.matter].dispersion &&
+        is_empty(to);
+}
+
+// Gas drifts sideways on empty at random, the source's hash decides so both cells agree
+bool drifts_on_empty(Matter from, Matter to, ivec2 from_pos) {
+    return can_drift_on_empty(from, to) &&
+        (hash(from_pos, DISPERSION_SALT + push_constants.dispersion_step) & 1u) == 0u;
+}
+
+/*
+Diagonal slides: Falling matter slides down (dy = -1) & gas slides up (dy = 1). A cell with both diagonals
+free picks one at random. An empty cell that two cells slide into picks one of them at random, the loser
+stays. Both the sliding cells & the empty cell evaluate the same rules, so they agree on who moves where.
+*/
+
+bool can_slide_to(ivec2 to_pos, Matter from, Matter from_blocker, int dy) {
+    if (!is_inside_sim_canvas(to_pos)) {
+        return false;
+    }
+    Matter to = read_matter(to_pos);
+    return dy < 0 ? slides_on_empty(from, to, from_blocker) : slides_up_on_empty(from, to, from_blocker);
+}
+
+// Direction the cell at pos would slide in without friction: -1 left, 1 right, 0 none
+int free_slide_dir(ivec2 pos, int dy) {
+    ivec2 blocker_pos = pos + ivec2(0, dy);
+    if (!is_inside_sim_canvas(pos) || !is_inside_sim_canvas(blocker_pos)) {
+        return 0;
+    }
+    Matter m = read_matter(pos);
+    Matter blocker = read_matter(blocker_pos);
+    bool left = can_slide_to(pos + ivec2(-1, dy), m, blocker, dy);
+    bool right = can_slide_to(pos + ivec2(1, dy), m, blocker, dy);
+    if (left && right) {
+        return (hash(pos, SLIDE_SALT) & 1u) == 0u ? -1 : 1;
+    }
+    return left ? -1 : (right ? 1 : 0);
+}
+
+// Direction the cell at pos slides in: -1 left, 1 right, 0 none
+int slide_dir(ivec2 pos, int dy) {
+    int dir = free_slide_dir(pos, dy);
+    return dir != 0 && holds_by_friction(read_matter(pos), pos) ? 0 : dir;
+}
+
+// Side the cell sliding into empty pos comes from: -1 left, 1 right, 0 none
+int slide_source_dir(ivec2 pos, int dy) {
+    bool from_left = slide_dir(pos + ivec2(-1, -dy), dy) == 1;
+    bool from_right = slide_dir(pos + ivec2(1, -dy), dy) == -1;
+    if (from_left && from_right) {
+        return (hash(pos, SLIDE_SALT + 1) & 1u) == 0u ? -1 : 1;
+    }
+    return from_left ? -1 : (from_right ? 1 : 0);
+}
+
+void slide_empty(ivec2 pos, int dy) {
+    Matter current = read_matter(pos);
+    Matter m = current;
+    if (is_empty(current)) {
+        int source_dir = slide_source_dir(pos, dy);
+        if (source_dir != 0) {
+            m = read_matter(pos + ivec2(source_dir, -dy));
+        }
+    } else {
+        int dir = slide_dir(pos, dy);
+        ivec2 to_pos = pos + ivec2(dir, dy);
+        if (dir != 0 && slide_source_dir(to_pos, dy) == -dir) {
+            m = read_matter(to_pos);
+        }
+    }
+    write_matter(pos, m);
+}
+
+// Gas with a lifetime fades a little each rise pass & becomes empty at the end of it
+Matter age_gas(Matter m) {
+    if (!is_gas(m) || matter_definitions[m.matter].lifetime == 0) {
+        return m;
+    }
+    uint lifetime = get_lifetime(m);
+    if (lifetime <= 1) {
+        return new_matter(empty_matter);
+    }
+    return set_lifetime(m, lifetime - 1);
+}
+
+// Resting on something, either matter or the bottom of the canvas
+bool is_supported(ivec2 pos) {
+    return is_at_border_bottom(pos) || !is_empty(get_neighbor(pos, DOWN));
+}
+
+bool disperses_on_empty(Matter from, Matter to, ivec2 from_pos) {
+    return is_liquid(from) && push_constants.dispersion_step < matter_definitions[from.matter].dispersion &&
+        is_empty(to) && is_supported(from_pos);
 }
 
 vec4 matter_color_to_vec4(uint color) {
@@ -123,5 +359,3 @@ vec4 matter_color_to_vec4(uint color) {
         float(color & uint(255)) / 255.0,
         1.0);
 }
-
-
@@ -1,11 +1,103 @@
 struct Matter {
     uint matter;
     uint color;
+    // Per cell state, bits 0-7: lifetime, bits 8-15: fall velocity
+    uint state;
 };
 
 Matter new_matter(uint matter) {
     Matter m;
     m.matter = (matter & uint(255));
     m.color = matter >> uint(8);
+    m.state = 0;
     return m;
 }
+
+// Cells are stored as (color << 8 | matter, state)
+Matter matter_from_cell(uvec2 cell) {
+    Matter m = new_matter(cell.x);
+    m.state = cell.y;
+    return m;
+}
+
+uvec2 matter_to_cell(Matter matter) {
+    return uvec2((matter.color << uint(8)) | matter.matter, matter.state);
+}
+
+#define LIFETIME_MASK 255
+
+uint get_lifetime(Matter m) {
+    return m.state & LIFETIME_MASK;
+}
+
+Matter set_lifetime(Matter m, uint lifetime) {
+    m.state = (m.state & ~uint(LIFETIME_MASK)) | (lifetime & LIFETIME_MASK);
+    return m;
+}
+
+// Fall velocity in 1/VELOCITY_SCALE cells per fall pass
+#define VELOCITY_SHIFT 8
+#define VELOCITY_MASK 255
+#define VELOCITY_SCALE 16
+
+uint get_velocity(Matter m) {
+    return (m.state >> VELOCITY_SHIFT) & VELOCITY_MASK;
+}
+
+Matter set_velocity(Matter m, uint velocity) {
+    m.state = (m.state & ~uint(VELOCITY_MASK << VELOCITY_SHIFT)) | ((velocity & VELOCITY_MASK) << VELOCITY_SHIFT);
+    return m;
+}
+
+/*
+Matter definitions, see MatterRegistry in matter.rs
+*/
+#define GRAVITY 1
+#define SLIDES 2
+#define STATIC 4
+#define LIQUID 8
+#define GAS 16
+#define BURNING 32
+
+struct MatterDefinition {
+    uint flags;
+    float color_variation;
+    uint dispersion;
+    uint lifetime;
+    float density;
+    // Chance a sliding cell holds its place instead of sliding (0-1)
+    float friction;
+    // Chance to catch fire from each burning neighbor per move step
+    float flammability;
+    // What burning matter leaves behind, as (color << 8 | matter)
+    uint burns_into;
+    // How much heat flows to & from neighbors per step (0-1)
+    float conductivity;
+    // Heat sources hold their cells at `temperature`
+    uint is_heat_source;
+    float temperature;
+    // Matter turns into `above_into` over `above_temperature` & into `below_into` under `below_temperature`
+    float above_temperature;
+    uint above_into;
+    float below_temperature;
+    uint below_into;
+};
+
+// What matter turns into when it touches other matter, see `reactions` in includes.glsl
+struct Reaction {
+    float probability;
+    // Matter it turns into as (color << 8 | matter)
+    uint into;
+};
+
+// A brush stroke: a line from `start` to `end` painted with a circle of `radius` of `matter`
+struct Stroke {
+    vec2 start;
+    vec2 end;
+    float radius;
+    // Matter as (color << 8 | matter)
+    uint matter;
+    // Cells the stroke can paint, `bounds_end` is exclusive
+    ivec2 bounds_origin;
+    ivec2 bounds_end;
+};
@@ -0,0 +1,38 @@
+#version 450
+
+#include "includes.glsl"
+
+// Cells are paired with a side neighbor & react with their pair. Pairs alternate between vertical &
+// horizontal and their parity each pass, each cell belongs to exactly one pair. Both cells of a pair roll
+// the same random number, so either both or neither of them react. The reaction table holds both sides
+// of each reaction.
+void react(ivec2 pos) {
+    uint orientation = (sim_step() + move_step()) % 4;
+    ivec2 axis = orientation % 2 == 0 ? ivec2(0, 1) : ivec2(1, 0);
+    int axis_pos = orientation % 2 == 0 ? pos.y : pos.x;
+    bool is_pair_first = (axis_pos + int(orientation / 2)) % 2 == 0;
+    ivec2 partner_pos = is_pair_first ? pos + axis : pos - axis;
+    ivec2 pair_pos = is_pair_first ? pos : partner_pos;
+
+    Matter current = read_matter(pos);
+    Matter m = current;
+    if (is_inside_sim_canvas(partner_pos)) {
+        Matter partner = read_matter(partner_pos);
+        Reaction reaction = reactions[current.matter * 256 + partner.matter];
+        if (reaction.probability > 0.0) {
+            mark_changed(pos);
+            if (random(pair_pos, REACTION_SALT) < reaction.probability) {
+                m = with_initial_state(reaction.into);
+            }
+        }
+    }
+    write_matter(pos, m);
+}
+
+void main() {
+    ivec2 pos = get_current_sim_pos();
+    if (!is_inside_sim_canvas(pos) || is_tile_asleep()) {
+        return;
+    }
+    react(pos);
+}
@@ -0,0 +1,25 @@
+#version 450
+
+#include "includes.glsl"
+
+// Mirror of fall_empty: gas rises on empty. Gas also ages here, once per move step
+void rise_empty(ivec2 pos) {
+    Matter current = read_matter(pos);
+    Matter down = get_neighbor(pos, DOWN);
+    Matter up = get_neighbor(pos, UP);
+    Matter m = current;
+    if (!is_at_border_bottom(pos) && rises_on_empty(down, current)) {
+        m = down;
+    } else if (!is_at_border_top(pos) && rises_on_empty(current, up)) {
+        m = up;
+    }
+    write_matter(pos, age_gas(m));
+}
+
+void main() {
+    ivec2 pos = get_current_sim_pos();
+    if (!is_inside_sim_canvas(pos) || is_tile_asleep()) {
+        return;
+    }
+    rise_empty(pos);
+}
@@ -0,0 +1,31 @@
+#version 450
+
+#include "includes.glsl"
+
+// Heavier matter sinks through lighter liquid & gas below it. Rows are paired as (y, y + 1) with
+// alternating parity, each cell belongs to exactly one pair, so a swap never conflicts with another swap
+void sink_lighter(ivec2 pos) {
+    Matter current = read_matter(pos);
+    Matter m = current;
+    bool is_pair_bottom = (uint(pos.y) + sim_step() + move_step()) % 2 == 0;
+    if (is_pair_bottom) {
+        Matter up = get_neighbor(pos, UP);
+        if (!is_at_border_top(pos) && sinks_through(up, current)) {
+            m = up;
+        }
+    } else {
+        Matter down = get_neighbor(pos, DOWN);
+        if (!is_at_border_bottom(pos) && sinks_through(current, down)) {
+            m = down;
+        }
+    }
+    write_matter(pos, m);
+}
+
+void main() {
+    ivec2 pos = get_current_sim_pos();
+    if (!is_inside_sim_canvas(pos) || is_tile_asleep()) {
+        return;
+    }
+    sink_lighter(pos);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+#include "includes.glsl"
+
+// Mirror of slide_down_empty: gas slides up diagonally on empty when it can't rise
+void main() {
+    ivec2 pos = get_current_sim_pos();
+    if (!is_inside_sim_canvas(pos) || is_tile_asleep()) {
+        return;
+    }
+    slide_empty(pos, 1);
+}
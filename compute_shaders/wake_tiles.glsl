@@ -0,0 +1,30 @@
+#version 450
+
+#include "includes.glsl"
+
+// Steps a tile stays awake after a change around it. Long enough for every pairing & direction of the
+// movement passes to get its turn, so a tile that falls asleep would have stayed the same.
+#define KEEP_AWAKE_STEPS 4u
+
+int num_tiles_y() {
+    return (canvas_size_y + int(gl_WorkGroupSize.y) - 1) / int(gl_WorkGroupSize.y);
+}
+
+// Runs once per tile at the start of a step. Tiles within `wake_radius` tiles of a tile that changed
+// during the last step are woken, others count down their steps until they fall asleep.
+void main() {
+    ivec2 tile = ivec2(gl_GlobalInvocationID.xy);
+    if (tile.x >= num_tiles_x() || tile.y >= num_tiles_y()) {
+        return;
+    }
+    int radius = int(push_constants.wake_radius);
+    bool changed = false;
+    for (int y = max(tile.y - radius, 0); y <= min(tile.y + radius, num_tiles_y() - 1); y++) {
+        for (int x = max(tile.x - radius, 0); x <= min(tile.x + radius, num_tiles_x() - 1); x++) {
+            changed = changed || tile_changed[get_tile_index(ivec2(x, y))] != 0u;
+        }
+    }
+    int index = get_tile_index(tile);
+    uint awake = tile_awake[index];
+    tile_awake[index] = changed ? KEEP_AWAKE_STEPS : (awake > 0u ? awake - 1u : 0u);
+}
@@ -1,175 +1,410 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
 
-use bevy::math::{IVec2, Vec2};
+use bevy::math::{IVec2, UVec2, Vec2};
+use bytemuck::{Pod, Zeroable};
 use vulkano::{
-    buffer::{BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer},
+    buffer::{BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer, TypedBufferAccess},
     command_buffer::{
-        AutoCommandBufferBuilder, CommandBufferUsage, PrimaryAutoCommandBuffer,
-        PrimaryCommandBuffer,
+        AutoCommandBufferBuilder, BufferCopy, CommandBufferExecFuture,
+        CommandBufferInheritanceInfo, CommandBufferUsage, CopyBufferInfoTyped,
+        CopyImageToBufferInfo, FillBufferInfo, PrimaryAutoCommandBuffer, PrimaryCommandBuffer,
+        SecondaryAutoCommandBuffer,
     },
     descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
     device::Queue,
     format::Format,
     image::{ImageUsage, StorageImage},
-    pipeline::{ComputePipeline, Pipeline, PipelineBindPoint},
-    sync::GpuFuture,
+    pipeline::{cache::PipelineCache, ComputePipeline, Pipeline, PipelineBindPoint},
+    shader::{ShaderCreationError, ShaderModule},
+    sync::{FenceSignalFuture, GpuFuture, NowFuture},
     DeviceSize,
 };
 use vulkano_util::renderer::DeviceImageView;
 
 use crate::{
-    matter::{MatterId, MatterWithColor},
+    grid::{GridRect, MatterGrid},
+    image_io::{color_image_from_linear_rgba, image_error_to_io, matter_id_image, MatterPalette},
+    matter::{MatterDefinitionGpu, MatterId, MatterRegistry, MatterWithColor, ReactionGpu},
+    query::{QueryReadback, QueryResult},
+    snapshot::Snapshot,
     utils::{create_compute_pipeline, storage_buffer_desc, storage_image_desc},
-    CANVAS_SIZE_X, CANVAS_SIZE_Y, LOCAL_SIZE_X, LOCAL_SIZE_Y, NUM_WORK_GROUPS_X, NUM_WORK_GROUPS_Y,
+    AMBIENT_TEMPERATURE, CANVAS_SIZE_X, CANVAS_SIZE_Y, LOCAL_SIZE_X, LOCAL_SIZE_Y,
 };
 
+type SubmitFuture = FenceSignalFuture<CommandBufferExecFuture<NowFuture, PrimaryAutoCommandBuffer>>;
+
 fn device_grid(
     compute_queue: &Arc<Queue>,
     width: u32,
     height: u32,
-) -> Arc<DeviceLocalBuffer<[u32]>> {
+) -> Arc<DeviceLocalBuffer<[MatterWithColor]>> {
     DeviceLocalBuffer::array(
         compute_queue.device().clone(),
         (width * height) as DeviceSize,
-        BufferUsage::storage_buffer() | BufferUsage::transfer_dst(),
+        BufferUsage::storage_buffer() | BufferUsage::transfer_src() | BufferUsage::transfer_dst(),
+        compute_queue.device().active_queue_families(),
+    )
+    .unwrap()
+}
+
+/// A brush stroke as uploaded to the kernels, must match `Stroke` in `matter.glsl`
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Zeroable, Pod)]
+struct StrokeGpu {
+    start: [f32; 2],
+    end: [f32; 2],
+    radius: f32,
+    /// Packed [`MatterWithColor::value`]
+    matter: u32,
+    /// Cells the stroke can paint, `bounds_end` is exclusive
+    bounds_origin: [i32; 2],
+    bounds_end: [i32; 2],
+}
+
+/// Counters of the current step as uploaded to the kernels, must match `StepStateBuffer` in `includes.glsl`
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Zeroable, Pod)]
+struct StepStateGpu {
+    sim_step: u32,
+    move_step: u32,
+    world_seed: u32,
+}
+
+/// Passes of a whole step, recorded once & executed again on every step with the same move steps starting
+/// from the same buffer orientation
+struct RecordedStep {
+    command_buffer: Arc<SecondaryAutoCommandBuffer>,
+    /// Movement passes of the step, move_step advances by this much
+    move_passes: u32,
+    /// Orientation of the buffers after the step
+    orientation: Orientation,
+}
+
+/// Which of the double buffers are swapped from how they were created. Each orientation has its own
+/// descriptor set.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+struct Orientation {
+    matter_swapped: bool,
+    temperature_swapped: bool,
+}
+
+impl Orientation {
+    fn index(&self) -> usize {
+        self.matter_swapped as usize | (self.temperature_swapped as usize) << 1
+    }
+}
+
+fn strokes_buffer(compute_queue: &Arc<Queue>, len: usize) -> Arc<CpuAccessibleBuffer<[StrokeGpu]>> {
+    CpuAccessibleBuffer::from_iter(
+        compute_queue.device().clone(),
+        BufferUsage::storage_buffer(),
+        false,
+        (0..len).map(|_| StrokeGpu::default()),
+    )
+    .unwrap()
+}
+
+fn device_tiles(compute_queue: &Arc<Queue>, num_tiles: [u32; 2]) -> Arc<DeviceLocalBuffer<[u32]>> {
+    DeviceLocalBuffer::array(
+        compute_queue.device().clone(),
+        (num_tiles[0] * num_tiles[1]) as DeviceSize,
+        BufferUsage::storage_buffer() | BufferUsage::transfer_src() | BufferUsage::transfer_dst(),
         compute_queue.device().active_queue_families(),
     )
     .unwrap()
 }
 
+fn device_temperature(
+    compute_queue: &Arc<Queue>,
+    width: u32,
+    height: u32,
+) -> Arc<DeviceLocalBuffer<[f32]>> {
+    DeviceLocalBuffer::array(
+        compute_queue.device().clone(),
+        (width * height) as DeviceSize,
+        BufferUsage::storage_buffer() | BufferUsage::transfer_src() | BufferUsage::transfer_dst(),
+        compute_queue.device().active_queue_families(),
+    )
+    .unwrap()
+}
+
+/// How many cells a change can spread in a move step without dispersion: falling reads up to 9 cells away
+/// (see `fall_empty.glsl`), the other passes at most 3 cells away each
+const MOVE_STEP_REACH: u32 = 24;
+
+/// Canvas & kernel dimensions of a simulator. Canvas sizes don't need to be multiples of the kernel
+/// sizes, invocations outside the canvas are skipped in the kernels.
+#[derive(Debug, Copy, Clone)]
+pub struct CASimulatorConfig {
+    pub canvas_size_x: u32,
+    pub canvas_size_y: u32,
+    pub local_size_x: u32,
+    pub local_size_y: u32,
+}
+
+impl Default for CASimulatorConfig {
+    fn default() -> Self {
+        CASimulatorConfig {
+            canvas_size_x: CANVAS_SIZE_X,
+            canvas_size_y: CANVAS_SIZE_Y,
+            local_size_x: LOCAL_SIZE_X,
+            local_size_y: LOCAL_SIZE_Y,
+        }
+    }
+}
+
+impl CASimulatorConfig {
+    /// Number of work groups needed to cover the whole canvas
+    pub fn num_work_groups(&self) -> [u32; 2] {
+        [
+            (self.canvas_size_x + self.local_size_x - 1) / self.local_size_x,
+            (self.canvas_size_y + self.local_size_y - 1) / self.local_size_y,
+        ]
+    }
+
+    /// Number of sleeping tiles the canvas is split into, one per work group
+    pub fn num_tiles(&self) -> [u32; 2] {
+        self.num_work_groups()
+    }
+}
+
 /// Cellular automata simulation pipeline
 pub struct CASimulator {
     compute_queue: Arc<Queue>,
+    config: CASimulatorConfig,
     fall_pipeline: Arc<ComputePipeline>,
+    sink_pipeline: Arc<ComputePipeline>,
     slide_pipeline: Arc<ComputePipeline>,
+    rise_pipeline: Arc<ComputePipeline>,
+    slide_up_pipeline: Arc<ComputePipeline>,
+    disperse_pipeline: Arc<ComputePipeline>,
+    burn_pipeline: Arc<ComputePipeline>,
+    heat_pipeline: Arc<ComputePipeline>,
+    react_pipeline: Arc<ComputePipeline>,
+    wake_tiles_pipeline: Arc<ComputePipeline>,
     color_pipeline: Arc<ComputePipeline>,
     draw_matter_pipeline: Arc<ComputePipeline>,
-    query_matter_pipeline: Arc<ComputePipeline>,
-    matter_in: Arc<DeviceLocalBuffer<[u32]>>,
-    matter_out: Arc<DeviceLocalBuffer<[u32]>>,
-    query_matter: Arc<CpuAccessibleBuffer<[u32]>>,
+    matter_in: Arc<DeviceLocalBuffer<[MatterWithColor]>>,
+    matter_out: Arc<DeviceLocalBuffer<[MatterWithColor]>>,
+    /// Temperature of each cell, double buffered like matter but only swapped by the heat pass
+    temperature_in: Arc<DeviceLocalBuffer<[f32]>>,
+    temperature_out: Arc<DeviceLocalBuffer<[f32]>>,
+    /// Tiles whose cells changed during the current step, see `includes.glsl`
+    tile_changed: Arc<DeviceLocalBuffer<[u32]>>,
+    /// Steps each tile stays awake, movement kernels skip tiles that are asleep
+    tile_awake: Arc<DeviceLocalBuffer<[u32]>>,
+    matter_registry: MatterRegistry,
+    matter_definitions: Arc<CpuAccessibleBuffer<[MatterDefinitionGpu]>>,
+    reactions: Arc<CpuAccessibleBuffer<[ReactionGpu]>>,
     image: DeviceImageView,
     pub sim_step: u32,
     move_step: u32,
-    draw_radius: f32,
-    draw_matter: MatterWithColor,
-    draw_pos_start: Vec2,
-    draw_pos_end: Vec2,
-    query_pos: IVec2,
+    /// Dispersion pass within a move step
+    dispersion_step: u32,
+    /// Seed of all random numbers in the kernels, see `hash` in `includes.glsl`
+    world_seed: u32,
+    /// Tiles woken around each changed tile, see `wake_tiles.glsl`
+    wake_radius: u32,
+    /// Counters of the current step, written before each submission
+    step_state: Arc<CpuAccessibleBuffer<StepStateGpu>>,
+    /// Movement passes so far in the step being recorded
+    move_pass: u32,
+    orientation: Orientation,
+    /// Descriptor sets of each orientation, created on first use
+    descriptor_sets: [Option<Arc<PersistentDescriptorSet>>; 4],
+    /// Step command buffers by move steps (`None` when paused) & orientation at the start of the step
+    recorded_steps: HashMap<(Option<u32>, Orientation), Arc<RecordedStep>>,
+    /// Strokes of the last drawing dispatch, grown to fit as needed
+    strokes: Arc<CpuAccessibleBuffer<[StrokeGpu]>>,
+    num_strokes: u32,
+    /// Strokes to draw at the start of the next step & the cells they cover
+    queued_strokes: Vec<StrokeGpu>,
+    queued_stroke_bounds: Option<GridRect>,
+    query_readback: QueryReadback,
+    queued_queries: Vec<IVec2>,
+    finished_queries: Vec<QueryResult>,
 }
 
 impl CASimulator {
-    /// Create new simulator pipeline for a compute queue. Ensure that canvas sizes are divisible by kernel sizes so no pixel
-    /// remains unsimulated.
-    pub fn new(compute_queue: Arc<Queue>) -> CASimulator {
-        // In order to not miss any pixels, the following must be true
-        assert_eq!(CANVAS_SIZE_X % LOCAL_SIZE_X, 0);
-        assert_eq!(CANVAS_SIZE_Y % LOCAL_SIZE_Y, 0);
-        let matter_in = device_grid(&compute_queue, CANVAS_SIZE_X, CANVAS_SIZE_Y);
-        let matter_out = device_grid(&compute_queue, CANVAS_SIZE_X, CANVAS_SIZE_Y);
-        let query_matter = CpuAccessibleBuffer::from_iter(
+    /// Create new simulator pipeline for a compute queue with given canvas & kernel sizes. Pipelines are
+    /// looked up from & added to `pipeline_cache` if given.
+    pub fn new(
+        compute_queue: Arc<Queue>,
+        config: CASimulatorConfig,
+        matter_registry: MatterRegistry,
+        pipeline_cache: Option<Arc<PipelineCache>>,
+    ) -> CASimulator {
+        assert!(config.canvas_size_x > 0 && config.canvas_size_y > 0);
+        assert!(config.local_size_x > 0 && config.local_size_y > 0);
+        let matter_in = device_grid(&compute_queue, config.canvas_size_x, config.canvas_size_y);
+        let matter_out = device_grid(&compute_queue, config.canvas_size_x, config.canvas_size_y);
+        let temperature_in =
+            device_temperature(&compute_queue, config.canvas_size_x, config.canvas_size_y);
+        let temperature_out =
+            device_temperature(&compute_queue, config.canvas_size_x, config.canvas_size_y);
+        let tile_changed = device_tiles(&compute_queue, config.num_tiles());
+        let tile_awake = device_tiles(&compute_queue, config.num_tiles());
+        let matter_definitions = CpuAccessibleBuffer::from_iter(
             compute_queue.device().clone(),
-            BufferUsage::storage_buffer() | BufferUsage::transfer_dst(),
+            BufferUsage::storage_buffer(),
             false,
-            vec![MatterWithColor::from(0).value],
+            matter_registry.gpu_definitions(),
         )
         .unwrap();
+        let reactions = CpuAccessibleBuffer::from_iter(
+            compute_queue.device().clone(),
+            BufferUsage::storage_buffer(),
+            false,
+            matter_registry.gpu_reactions().iter().copied(),
+        )
+        .unwrap();
+        let step_state = CpuAccessibleBuffer::from_data(
+            compute_queue.device().clone(),
+            BufferUsage::storage_buffer(),
+            false,
+            StepStateGpu::default(),
+        )
+        .unwrap();
+        let strokes = strokes_buffer(&compute_queue, 1);
 
         // Assumes all shaders that are loaded with specialication constants have the same constants
         let spec_const = fall_empty_cs::SpecializationConstants {
-            canvas_size_x: CANVAS_SIZE_X as i32,
-            canvas_size_y: CANVAS_SIZE_Y as i32,
-            empty_matter: MatterWithColor::new(MatterId::Empty).value,
-            constant_3: LOCAL_SIZE_X,
-            constant_4: LOCAL_SIZE_Y,
+            canvas_size_x: config.canvas_size_x as i32,
+            canvas_size_y: config.canvas_size_y as i32,
+            empty_matter: matter_registry.matter_with_color(MatterId::EMPTY).value,
+            constant_3: config.local_size_x,
+            constant_4: config.local_size_y,
+            ambient_temperature: AMBIENT_TEMPERATURE,
         };
 
         // Create pipelines
         let (
             fall_pipeline,
+            sink_pipeline,
             slide_pipeline,
+            rise_pipeline,
+            slide_up_pipeline,
+            disperse_pipeline,
+            burn_pipeline,
+            heat_pipeline,
+            react_pipeline,
+            wake_tiles_pipeline,
             color_pipeline,
             draw_matter_pipeline,
-            query_matter_pipeline,
         ) = {
-            let fall_shader = fall_empty_cs::load(compute_queue.device().clone()).unwrap();
-            let slide_shader = slide_down_empty_cs::load(compute_queue.device().clone()).unwrap();
-            let color_shader = color_cs::load(compute_queue.device().clone()).unwrap();
-            let draw_matter_shader = draw_matter_cs::load(compute_queue.device().clone()).unwrap();
-            let query_matter_shader =
-                query_matter_cs::load(compute_queue.device().clone()).unwrap();
             // This must match the shader & inputs in dispatch
             let descriptor_layout = [
                 (0, storage_buffer_desc()),
                 (1, storage_buffer_desc()),
                 (2, storage_image_desc()),
                 (3, storage_buffer_desc()),
+                (4, storage_buffer_desc()),
+                (5, storage_buffer_desc()),
+                (6, storage_buffer_desc()),
+                (7, storage_buffer_desc()),
+                (8, storage_buffer_desc()),
+                (9, storage_buffer_desc()),
+                (10, storage_buffer_desc()),
             ];
-            (
-                create_compute_pipeline(
-                    compute_queue.clone(),
-                    fall_shader.entry_point("main").unwrap(),
-                    descriptor_layout.to_vec(),
-                    &spec_const,
-                ),
-                create_compute_pipeline(
-                    compute_queue.clone(),
-                    slide_shader.entry_point("main").unwrap(),
-                    descriptor_layout.to_vec(),
-                    &spec_const,
-                ),
-                create_compute_pipeline(
-                    compute_queue.clone(),
-                    color_shader.entry_point("main").unwrap(),
-                    descriptor_layout.to_vec(),
-                    &spec_const,
-                ),
-                create_compute_pipeline(
-                    compute_queue.clone(),
-                    draw_matter_shader.entry_point("main").unwrap(),
-                    descriptor_layout.to_vec(),
-                    &spec_const,
-                ),
+            let pipeline = |shader: Result<Arc<ShaderModule>, ShaderCreationError>| {
                 create_compute_pipeline(
                     compute_queue.clone(),
-                    query_matter_shader.entry_point("main").unwrap(),
+                    shader.unwrap().entry_point("main").unwrap(),
                     descriptor_layout.to_vec(),
                     &spec_const,
-                ),
+                    pipeline_cache.clone(),
+                )
+            };
+            let device = compute_queue.device();
+            (
+                pipeline(fall_empty_cs::load(device.clone())),
+                pipeline(sink_lighter_cs::load(device.clone())),
+                pipeline(slide_down_empty_cs::load(device.clone())),
+                pipeline(rise_empty_cs::load(device.clone())),
+                pipeline(slide_up_empty_cs::load(device.clone())),
+                pipeline(disperse_empty_cs::load(device.clone())),
+                pipeline(burn_cs::load(device.clone())),
+                pipeline(heat_cs::load(device.clone())),
+                pipeline(react_cs::load(device.clone())),
+                pipeline(wake_tiles_cs::load(device.clone())),
+                pipeline(color_cs::load(device.clone())),
+                pipeline(draw_matter_cs::load(device.clone())),
             )
         };
         // Create color image
         let image = StorageImage::general_purpose_image_view(
             compute_queue.clone(),
-            [CANVAS_SIZE_X, CANVAS_SIZE_Y],
+            [config.canvas_size_x, config.canvas_size_y],
             Format::R8G8B8A8_UNORM,
             ImageUsage {
                 sampled: true,
+                transfer_src: true,
                 transfer_dst: true,
                 storage: true,
                 ..ImageUsage::none()
             },
         )
         .unwrap();
-        CASimulator {
-            compute_queue,
+        let simulator = CASimulator {
+            compute_queue: compute_queue.clone(),
+            config,
             fall_pipeline,
+            sink_pipeline,
             slide_pipeline,
+            rise_pipeline,
+            slide_up_pipeline,
+            disperse_pipeline,
+            burn_pipeline,
+            heat_pipeline,
+            react_pipeline,
+            wake_tiles_pipeline,
             color_pipeline,
             draw_matter_pipeline,
-            query_matter_pipeline,
             matter_in,
             matter_out,
-            query_matter,
+            temperature_in,
+            temperature_out,
+            tile_changed,
+            tile_awake,
+            matter_registry,
+            matter_definitions,
+            reactions,
             image,
             sim_step: 0,
             move_step: 0,
-            draw_radius: 0.0,
-            draw_matter: MatterWithColor::from(0),
-            draw_pos_start: Vec2::new(0.0, 0.0),
-            draw_pos_end: Vec2::new(0.0, 0.0),
-            query_pos: IVec2::new(0, 0),
-        }
+            dispersion_step: 0,
+            world_seed: 0,
+            wake_radius: 1,
+            step_state,
+            move_pass: 0,
+            orientation: Orientation::default(),
+            descriptor_sets: Default::default(),
+            recorded_steps: HashMap::new(),
+            strokes,
+            num_strokes: 0,
+            queued_strokes: vec![],
+            queued_stroke_bounds: None,
+            query_readback: QueryReadback::new(compute_queue.device().clone(), 64),
+            queued_queries: vec![],
+            finished_queries: vec![],
+        };
+        simulator.reset_temperature();
+        let mut command_buffer_builder = simulator.command_buffer_builder();
+        simulator.wake_all_tiles(&mut command_buffer_builder);
+        simulator.execute(command_buffer_builder, true);
+        simulator
+    }
+
+    /// Seed the random numbers of the kernels. Simulations with the same seed, steps & drawing run the
+    /// same.
+    pub fn set_seed(&mut self, seed: u32) {
+        self.world_seed = seed;
     }
 
     /// Get canvas image for rendering
@@ -177,9 +412,27 @@ impl CASimulator {
         self.image.clone()
     }
 
+    /// Matter this simulator was created with
+    pub fn matter_registry(&self) -> &MatterRegistry {
+        &self.matter_registry
+    }
+
+    /// Canvas size in pixels
+    pub fn canvas_size(&self) -> UVec2 {
+        UVec2::new(self.config.canvas_size_x, self.config.canvas_size_y)
+    }
+
     /// Are we within simulation bounds?
     fn is_inside(&self, pos: IVec2) -> bool {
-        pos.x >= 0 && pos.x < CANVAS_SIZE_X as i32 && pos.y >= 0 && pos.y < CANVAS_SIZE_Y as i32
+        pos.x >= 0
+            && pos.x < self.config.canvas_size_x as i32
+            && pos.y >= 0
+            && pos.y < self.config.canvas_size_y as i32
+    }
+
+    /// Index of a canvas position in the matter buffers
+    fn get_index(&self, pos: UVec2) -> DeviceSize {
+        (pos.y * self.config.canvas_size_x + pos.x) as DeviceSize
     }
 
     fn command_buffer_builder(&self) -> AutoCommandBufferBuilder<PrimaryAutoCommandBuffer> {
@@ -191,75 +444,514 @@ impl CASimulator {
         .unwrap()
     }
 
+    fn submit(
+        &self,
+        command_buffer_builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> SubmitFuture {
+        let command_buffer = command_buffer_builder.build().unwrap();
+        let finished = command_buffer.execute(self.compute_queue.clone()).unwrap();
+        finished.then_signal_fence_and_flush().unwrap()
+    }
+
     fn execute(
         &self,
         command_buffer_builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
         wait: bool,
     ) {
-        let command_buffer = command_buffer_builder.build().unwrap();
-        let finished = command_buffer.execute(self.compute_queue.clone()).unwrap();
-        let future = finished.then_signal_fence_and_flush().unwrap();
+        let future = self.submit(command_buffer_builder);
         if wait {
             future.wait(None).unwrap();
         }
     }
 
-    /// Query matter at pos
+    /// Query matter at pos, waiting for the result. Prefer `queue_query` for per frame queries.
+    #[cfg(test)]
     pub fn query_matter(&mut self, pos: IVec2) -> Option<MatterId> {
         if self.is_inside(pos) {
-            self.query_pos = pos;
-            // Build command buffer
-            let mut command_buffer_builder = self.command_buffer_builder();
-
-            // Dispatch
-            self.dispatch(
-                &mut command_buffer_builder,
-                self.query_matter_pipeline.clone(),
+            // Copy the cell to a readback buffer
+            let staging = CpuAccessibleBuffer::from_iter(
+                self.compute_queue.device().clone(),
+                BufferUsage::transfer_dst(),
                 false,
-            );
+                [MatterWithColor::default()],
+            )
+            .unwrap();
+            let mut copy_info =
+                CopyBufferInfoTyped::buffers(self.matter_in.clone(), staging.clone());
+            copy_info.regions[0] = BufferCopy {
+                src_offset: self.get_index(pos.as_uvec2()),
+                dst_offset: 0,
+                size: 1,
+                ..Default::default()
+            };
+            let mut command_buffer_builder = self.command_buffer_builder();
+            command_buffer_builder.copy_buffer(copy_info).unwrap();
 
             // Execute & finish (wait)
             self.execute(command_buffer_builder, true);
 
             // Read result
-            let query_matter = self.query_matter.read().unwrap();
-            Some(MatterWithColor::from(query_matter[0]).matter_id())
+            let cells = staging.read().unwrap();
+            Some(cells[0].matter_id())
         } else {
             None
         }
     }
 
-    /// Draw matter line with given radius
+    /// Queue a matter query at pos. Queued queries are submitted together by `submit_queries`, and their
+    /// results are returned by `poll_queries` once the next `step` has copied them.
+    pub fn queue_query(&mut self, pos: IVec2) {
+        if self.is_inside(pos) {
+            self.queued_queries.push(pos);
+        } else {
+            self.finished_queries.push(QueryResult {
+                pos,
+                matter: None,
+            });
+        }
+    }
+
+    /// Submit all queued queries into the readback buffer. Their cells are copied in the next step's
+    /// submission, after its passes, so queries never hold up writes to the grid. Queries submitted
+    /// between two steps share the readback buffer.
+    pub fn submit_queries(&mut self) {
+        if self.queued_queries.is_empty() {
+            return;
+        }
+        // Keep the results of the last copy so we can reuse its buffer
+        if self.query_readback.is_ready() {
+            let results = self.query_readback.take_results();
+            self.finished_queries.extend(results);
+        }
+        self.query_readback
+            .positions
+            .append(&mut self.queued_queries);
+    }
+
+    /// Take the results of all queries whose copy has finished
+    pub fn poll_queries(&mut self) -> Vec<QueryResult> {
+        if self.query_readback.is_ready() {
+            let results = self.query_readback.take_results();
+            self.finished_queries.extend(results);
+        }
+        std::mem::take(&mut self.finished_queries)
+    }
+
+    /// Record the copies of all submitted queries from the grid into the readback buffer
+    fn record_query_copies(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        if !self.query_readback.is_pending() {
+            return;
+        }
+        let len = self.query_readback.positions.len();
+        self.query_readback
+            .reserve(self.compute_queue.device().clone(), len);
+        let readback = &self.query_readback;
+        let mut copy_info =
+            CopyBufferInfoTyped::buffers(self.matter_in.clone(), readback.buffer.clone());
+        copy_info.regions = readback
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(i, pos)| BufferCopy {
+                src_offset: self.get_index(pos.as_uvec2()),
+                dst_offset: i as DeviceSize,
+                size: 1,
+                ..Default::default()
+            })
+            .collect();
+        builder.copy_buffer(copy_info).unwrap();
+        self.query_readback.is_copied = true;
+    }
+
+    /// Read the whole canvas back to the CPU
+    pub fn read_grid(&self) -> MatterGrid {
+        let canvas_size = self.canvas_size();
+        self.read_region(GridRect::new(UVec2::ZERO, canvas_size.x, canvas_size.y))
+    }
+
+    /// Read a rectangle of the canvas back to the CPU. The rectangle must be within the canvas.
+    pub fn read_region(&self, rect: GridRect) -> MatterGrid {
+        assert!(rect.is_inside(self.canvas_size()));
+        if rect.is_empty() {
+            return MatterGrid::new(rect.width, rect.height, vec![]);
+        }
+        let staging = CpuAccessibleBuffer::from_iter(
+            self.compute_queue.device().clone(),
+            BufferUsage::transfer_dst(),
+            true,
+            (0..rect.width * rect.height).map(|_| MatterWithColor::default()),
+        )
+        .unwrap();
+        // Copy row by row, rows of the region are not contiguous in the canvas
+        let mut copy_info = CopyBufferInfoTyped::buffers(self.matter_in.clone(), staging.clone());
+        copy_info.regions = (0..rect.height)
+            .map(|row| BufferCopy {
+                src_offset: self.get_index(rect.origin + UVec2::new(0, row)),
+                dst_offset: (row * rect.width) as DeviceSize,
+                size: rect.width as DeviceSize,
+                ..Default::default()
+            })
+            .collect();
+        let mut command_buffer_builder = self.command_buffer_builder();
+        command_buffer_builder.copy_buffer(copy_info).unwrap();
+
+        // Execute & finish (wait)
+        self.execute(command_buffer_builder, true);
+
+        let cells = staging.read().unwrap();
+        MatterGrid::new(rect.width, rect.height, cells.to_vec())
+    }
+
+    /// Write cells row by row (starting from the bottom row) into a `width` x `height` rectangle of the
+    /// canvas at `origin`. The rectangle must be within the canvas. The color image is updated on next
+    /// step.
+    pub fn write_region(
+        &mut self,
+        origin: UVec2,
+        width: u32,
+        height: u32,
+        cells: &[MatterWithColor],
+    ) {
+        let rect = GridRect::new(origin, width, height);
+        assert!(rect.is_inside(self.canvas_size()));
+        assert_eq!(cells.len(), (width * height) as usize);
+        if rect.is_empty() {
+            return;
+        }
+        let staging = CpuAccessibleBuffer::from_iter(
+            self.compute_queue.device().clone(),
+            BufferUsage::transfer_src(),
+            false,
+            cells.iter().copied(),
+        )
+        .unwrap();
+        let mut copy_info = CopyBufferInfoTyped::buffers(staging, self.matter_in.clone());
+        copy_info.regions = (0..height)
+            .map(|row| BufferCopy {
+                src_offset: (row * width) as DeviceSize,
+                dst_offset: self.get_index(origin + UVec2::new(0, row)),
+                size: width as DeviceSize,
+                ..Default::default()
+            })
+            .collect();
+        let mut command_buffer_builder = self.command_buffer_builder();
+        command_buffer_builder.copy_buffer(copy_info).unwrap();
+        self.wake_all_tiles(&mut command_buffer_builder);
+
+        // Execute & finish (no need to wait)
+        self.execute(command_buffer_builder, false);
+    }
+
+    /// Fill a rectangle of the canvas with matter of its default color
+    pub fn fill_region(&mut self, rect: GridRect, matter: MatterId) {
+        let cells = vec![
+            self.matter_registry.matter_with_color(matter);
+            (rect.width * rect.height) as usize
+        ];
+        self.write_region(rect.origin, rect.width, rect.height, &cells);
+    }
+
+    /// Save the simulation state to a snapshot file
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let grid = self.read_grid();
+        let snapshot = Snapshot {
+            width: grid.width(),
+            height: grid.height(),
+            sim_step: self.sim_step,
+            move_step: self.move_step,
+            world_seed: self.world_seed,
+            cells: grid.cells().to_vec(),
+            temperature: self.read_temperature(),
+        };
+        let mut writer = BufWriter::new(File::create(path)?);
+        snapshot.write(&mut writer, &self.matter_registry)?;
+        writer.flush()
+    }
+
+    /// Load the simulation state from a snapshot file. The snapshot must have the same size as our
+    /// canvas.
+    pub fn load_snapshot(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let snapshot = Snapshot::read(BufReader::new(File::open(path)?), &self.matter_registry)?;
+        let canvas_size = self.canvas_size();
+        if snapshot.width != canvas_size.x || snapshot.height != canvas_size.y {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Snapshot size ({},{}) does not match canvas size ({},{})",
+                    snapshot.width, snapshot.height, canvas_size.x, canvas_size.y
+                ),
+            ));
+        }
+        self.write_region(
+            UVec2::ZERO,
+            snapshot.width,
+            snapshot.height,
+            &snapshot.cells,
+        );
+        self.sim_step = snapshot.sim_step;
+        self.move_step = snapshot.move_step;
+        self.world_seed = snapshot.world_seed;
+        self.write_temperature(&snapshot.temperature);
+        Ok(())
+    }
+
+    /// Read the temperature of every cell back to the CPU, row by row starting from the bottom row
+    pub fn read_temperature(&self) -> Vec<f32> {
+        let canvas_size = self.canvas_size();
+        let staging = CpuAccessibleBuffer::from_iter(
+            self.compute_queue.device().clone(),
+            BufferUsage::transfer_dst(),
+            true,
+            (0..canvas_size.x * canvas_size.y).map(|_| 0.0f32),
+        )
+        .unwrap();
+        let mut command_buffer_builder = self.command_buffer_builder();
+        command_buffer_builder
+            .copy_buffer(CopyBufferInfoTyped::buffers(
+                self.temperature_in.clone(),
+                staging.clone(),
+            ))
+            .unwrap();
+
+        // Execute & finish (wait)
+        self.execute(command_buffer_builder, true);
+
+        let temperature = staging.read().unwrap();
+        temperature.to_vec()
+    }
+
+    /// Write the temperature of every cell, row by row starting from the bottom row
+    fn write_temperature(&self, temperature: &[f32]) {
+        let canvas_size = self.canvas_size();
+        assert_eq!(temperature.len(), (canvas_size.x * canvas_size.y) as usize);
+        let staging = CpuAccessibleBuffer::from_iter(
+            self.compute_queue.device().clone(),
+            BufferUsage::transfer_src(),
+            false,
+            temperature.iter().copied(),
+        )
+        .unwrap();
+        let mut command_buffer_builder = self.command_buffer_builder();
+        command_buffer_builder
+            .copy_buffer(CopyBufferInfoTyped::buffers(
+                staging,
+                self.temperature_in.clone(),
+            ))
+            .unwrap();
+
+        // Execute & finish (wait)
+        self.execute(command_buffer_builder, true);
+    }
+
+    /// Load an image into the canvas with its bottom left corner at `origin`, pixels mapped to matter
+    /// by `palette`. The image must fit within the canvas.
+    pub fn import_png(
+        &mut self,
+        path: impl AsRef<Path>,
+        origin: UVec2,
+        palette: &MatterPalette,
+    ) -> io::Result<()> {
+        let image = image::open(path).map_err(image_error_to_io)?.to_rgba8();
+        let grid = palette.matter_grid(&image)?;
+        let rect = GridRect::new(origin, grid.width(), grid.height());
+        if !rect.is_inside(self.canvas_size()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Image of size ({},{}) at ({},{}) does not fit canvas",
+                    grid.width(),
+                    grid.height(),
+                    origin.x,
+                    origin.y
+                ),
+            ));
+        }
+        self.write_region(origin, grid.width(), grid.height(), grid.cells());
+        Ok(())
+    }
+
+    /// Save the canvas color image as a png. The colors are those of the last step.
+    pub fn export_color_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let canvas_size = self.canvas_size();
+        let staging = CpuAccessibleBuffer::from_iter(
+            self.compute_queue.device().clone(),
+            BufferUsage::transfer_dst(),
+            true,
+            (0..canvas_size.x * canvas_size.y * 4).map(|_| 0u8),
+        )
+        .unwrap();
+        let mut command_buffer_builder = self.command_buffer_builder();
+        command_buffer_builder
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                self.image.image().clone(),
+                staging.clone(),
+            ))
+            .unwrap();
+
+        // Execute & finish (wait)
+        self.execute(command_buffer_builder, true);
+
+        let pixels = staging.read().unwrap();
+        color_image_from_linear_rgba(canvas_size.x, canvas_size.y, &pixels)
+            .save(path)
+            .map_err(image_error_to_io)
+    }
+
+    /// Save a png of the canvas where each matter id has its own distinct color
+    pub fn export_matter_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        matter_id_image(&self.read_grid())
+            .save(path)
+            .map_err(image_error_to_io)
+    }
+
+    /// Queue a line of matter with given radius. Queued strokes are drawn in order, later ones over
+    /// earlier ones, in one dispatch at the start of the next `step`.
+    pub fn queue_stroke(&mut self, start: Vec2, end: Vec2, radius: f32, matter: MatterId) {
+        if let Some(rect) = GridRect::stroke_bounds(start, end, radius, self.canvas_size()) {
+            self.queued_stroke_bounds = Some(
+                self.queued_stroke_bounds
+                    .map_or(rect, |bounds| bounds.union(&rect)),
+            );
+            self.queued_strokes.push(StrokeGpu {
+                start: start.into(),
+                end: end.into(),
+                radius,
+                matter: self.matter_registry.matter_with_color(matter).value,
+                bounds_origin: rect.origin.as_ivec2().into(),
+                bounds_end: rect.end().as_ivec2().into(),
+            });
+        }
+    }
+
+    /// Draw matter line with given radius right away, after any queued strokes. Prefer `queue_stroke`
+    /// for per frame drawing.
     pub fn draw_matter(&mut self, start: Vec2, end: Vec2, radius: f32, matter: MatterId) {
-        // Update our variables to be used as push constants
-        self.draw_pos_start = start;
-        self.draw_pos_end = end;
-        self.draw_matter = MatterWithColor::new(matter);
-        self.draw_radius = radius;
+        self.queue_stroke(start, end, radius, matter);
+        self.write_step_state();
+        self.submit_queued_strokes();
+    }
 
+    /// Draw all queued strokes right away, in a submission of their own
+    fn submit_queued_strokes(&mut self) {
+        if self.queued_strokes.is_empty() {
+            return;
+        }
         // Build command buffer
         let mut command_buffer_builder = self.command_buffer_builder();
 
         // Dispatch
-        self.dispatch(
-            &mut command_buffer_builder,
-            self.draw_matter_pipeline.clone(),
-            false,
-        );
+        self.draw_queued_strokes(&mut command_buffer_builder);
 
         // Execute & finish (no need to wait)
         self.execute(command_buffer_builder, false);
     }
 
+    /// Draw all queued strokes in one dispatch of the work groups covering them. Each cell skips the
+    /// strokes whose bounds don't contain it, so strokes far apart cost each other little.
+    fn draw_queued_strokes(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        let rect = match self.queued_stroke_bounds.take() {
+            Some(rect) => rect,
+            None => return,
+        };
+        let strokes = std::mem::take(&mut self.queued_strokes);
+        self.num_strokes = strokes.len() as u32;
+        if self.strokes.len() < strokes.len() as DeviceSize {
+            self.strokes = strokes_buffer(&self.compute_queue, strokes.len().next_power_of_two());
+            // Descriptor sets & the steps recorded with them refer to the old strokes buffer
+            self.descriptor_sets = Default::default();
+            self.recorded_steps.clear();
+        }
+        self.strokes.write().unwrap()[..strokes.len()].copy_from_slice(&strokes);
+        self.dispatch_work_groups(
+            builder,
+            self.draw_matter_pipeline.clone(),
+            rect.origin.as_ivec2(),
+            [
+                (rect.width + self.config.local_size_x - 1) / self.config.local_size_x,
+                (rect.height + self.config.local_size_y - 1) / self.config.local_size_y,
+            ],
+            false,
+        );
+    }
+
     /// Step simulation
     pub fn step(&mut self, move_steps: u32, is_paused: bool) {
+        self.write_step_state();
         let mut command_buffer_builder = self.command_buffer_builder();
 
-        if !is_paused {
+        // Drawing marks the tiles it changes, so it must happen before waking them
+        self.draw_queued_strokes(&mut command_buffer_builder);
+
+        // Steps only differ by their counters, so their passes are recorded once & reused
+        let move_steps = if is_paused { None } else { Some(move_steps) };
+        let key = (move_steps, self.orientation);
+        let recorded = match self.recorded_steps.get(&key) {
+            Some(recorded) => recorded.clone(),
+            None => {
+                let recorded = Arc::new(self.record_step(move_steps));
+                self.recorded_steps.insert(key, recorded.clone());
+                recorded
+            }
+        };
+        command_buffer_builder
+            .execute_commands(recorded.command_buffer.clone())
+            .unwrap();
+
+        self.set_orientation(recorded.orientation);
+        // Submitted queries read the grid the step leaves behind
+        self.record_query_copies(&mut command_buffer_builder);
+
+        // Execute & finish (wait). Query copies ride on this synchronous submission, so their results are
+        // ready to be polled once the step returns, & the step state is free to be written for the next.
+        self.execute(command_buffer_builder, true);
+
+        self.move_step += recorded.move_passes;
+        self.sim_step += 1;
+    }
+
+    /// Record the passes of a step with `move_steps`, `None` when paused. Leaves our buffers in the
+    /// orientation they were in, the step swaps them once it's executed.
+    fn record_step(&mut self, move_steps: Option<u32>) -> RecordedStep {
+        let orientation = self.orientation;
+        let mut command_buffer_builder = AutoCommandBufferBuilder::secondary(
+            self.compute_queue.device().clone(),
+            self.compute_queue.family(),
+            CommandBufferUsage::MultipleSubmit,
+            CommandBufferInheritanceInfo::default(),
+        )
+        .unwrap();
+
+        self.move_pass = 0;
+        if let Some(move_steps) = move_steps {
+            self.wake_tiles(&mut command_buffer_builder, move_steps);
             for _ in 0..move_steps {
                 self.step_movement(&mut command_buffer_builder, self.fall_pipeline.clone());
+                // Twice, so both row pairings of the sink pass get their turn each move step
+                self.step_movement(&mut command_buffer_builder, self.sink_pipeline.clone());
+                self.step_movement(&mut command_buffer_builder, self.sink_pipeline.clone());
                 self.step_movement(&mut command_buffer_builder, self.slide_pipeline.clone());
+                self.step_movement(&mut command_buffer_builder, self.rise_pipeline.clone());
+                self.step_movement(&mut command_buffer_builder, self.slide_up_pipeline.clone());
+                self.step_dispersion(&mut command_buffer_builder);
+                // Burning & reactions don't move matter, so they don't advance move_step either
+                self.dispatch(
+                    &mut command_buffer_builder,
+                    self.burn_pipeline.clone(),
+                    true,
+                );
+                self.dispatch(
+                    &mut command_buffer_builder,
+                    self.react_pipeline.clone(),
+                    true,
+                );
             }
+            self.step_heat(&mut command_buffer_builder);
         }
 
         // Finally color the image
@@ -269,60 +961,213 @@ impl CASimulator {
             false,
         );
 
-        // Execute & finish (no need to wait)
-        self.execute(command_buffer_builder, false);
+        let recorded = RecordedStep {
+            command_buffer: Arc::new(command_buffer_builder.build().unwrap()),
+            move_passes: self.move_pass,
+            orientation: self.orientation,
+        };
+        self.move_pass = 0;
+        self.set_orientation(orientation);
+        recorded
+    }
 
-        self.sim_step += 1;
+    /// Upload the counters the kernels read, see `StepStateBuffer` in `includes.glsl`. Earlier
+    /// submissions have finished, as their futures wait when dropped.
+    fn write_step_state(&self) {
+        *self.step_state.write().unwrap() = StepStateGpu {
+            sim_step: self.sim_step,
+            move_step: self.move_step,
+            world_seed: self.world_seed,
+        };
     }
 
-    /// Step a movement pipeline. move_step affects the order of sliding direction
+    /// Step a movement pipeline. Each movement pass gets its own random numbers & pairings through
+    /// move_step
     fn step_movement(
         &mut self,
-        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        builder: &mut AutoCommandBufferBuilder<SecondaryAutoCommandBuffer>,
         pipeline: Arc<ComputePipeline>,
     ) {
         self.dispatch(builder, pipeline.clone(), true);
-        self.move_step += 1;
+        self.move_pass += 1;
+    }
+
+    /// Spread liquids & drift gases sideways, one cell per pass. All passes of a move step use the same
+    /// direction, thus they don't advance move_step.
+    fn step_dispersion(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<SecondaryAutoCommandBuffer>,
+    ) {
+        for dispersion_step in 0..self.matter_registry.max_dispersion() {
+            self.dispersion_step = dispersion_step;
+            self.dispatch(builder, self.disperse_pipeline.clone(), true);
+        }
+    }
+
+    /// Diffuse heat & change matter at its threshold temperatures. Swaps both matter & temperature. Heat
+    /// stays where it is when matter moves, see `heat.glsl`.
+    fn step_heat(&mut self, builder: &mut AutoCommandBufferBuilder<SecondaryAutoCommandBuffer>) {
+        self.dispatch(builder, self.heat_pipeline.clone(), true);
+        std::mem::swap(&mut self.temperature_in, &mut self.temperature_out);
+        self.orientation.temperature_swapped = !self.orientation.temperature_swapped;
     }
 
-    /// Append a pipeline dispatch to our command buffer
+    /// Swap our double buffers to match `orientation`
+    fn set_orientation(&mut self, orientation: Orientation) {
+        if self.orientation.matter_swapped != orientation.matter_swapped {
+            std::mem::swap(&mut self.matter_in, &mut self.matter_out);
+        }
+        if self.orientation.temperature_swapped != orientation.temperature_swapped {
+            std::mem::swap(&mut self.temperature_in, &mut self.temperature_out);
+        }
+        self.orientation = orientation;
+    }
+
+    /// Wake the tiles around changes of the last step & forget the changes. Matter can move further with
+    /// more move steps, the wake radius must cover how far it can get from a changed tile within a step.
+    fn wake_tiles(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<SecondaryAutoCommandBuffer>,
+        move_steps: u32,
+    ) {
+        let reach = move_steps * (MOVE_STEP_REACH + 2 * self.matter_registry.max_dispersion());
+        let tile_size = self.config.local_size_x.min(self.config.local_size_y);
+        self.wake_radius = (reach + tile_size - 1) / tile_size;
+        let num_tiles = self.config.num_tiles();
+        self.dispatch_work_groups(
+            builder,
+            self.wake_tiles_pipeline.clone(),
+            IVec2::ZERO,
+            [
+                (num_tiles[0] + self.config.local_size_x - 1) / self.config.local_size_x,
+                (num_tiles[1] + self.config.local_size_y - 1) / self.config.local_size_y,
+            ],
+            false,
+        );
+        builder
+            .fill_buffer(FillBufferInfo::dst_buffer(self.tile_changed.clone()))
+            .unwrap();
+    }
+
+    /// Mark every tile changed, so all are awake on the next step. Needed whenever cells are written
+    /// from outside the kernels.
+    fn wake_all_tiles(&self, builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        builder
+            .fill_buffer(FillBufferInfo {
+                data: 1,
+                ..FillBufferInfo::dst_buffer(self.tile_changed.clone())
+            })
+            .unwrap();
+    }
+
+    /// Number of tiles that are awake, read back from the last step
+    #[cfg(test)]
+    pub fn num_awake_tiles(&self) -> usize {
+        let num_tiles = self.config.num_tiles();
+        let staging = CpuAccessibleBuffer::from_iter(
+            self.compute_queue.device().clone(),
+            BufferUsage::transfer_dst(),
+            true,
+            (0..num_tiles[0] * num_tiles[1]).map(|_| 0u32),
+        )
+        .unwrap();
+        let mut command_buffer_builder = self.command_buffer_builder();
+        command_buffer_builder
+            .copy_buffer(CopyBufferInfoTyped::buffers(
+                self.tile_awake.clone(),
+                staging.clone(),
+            ))
+            .unwrap();
+
+        // Execute & finish (wait)
+        self.execute(command_buffer_builder, true);
+
+        let tiles = staging.read().unwrap();
+        tiles.iter().filter(|&&awake| awake > 0).count()
+    }
+
+    /// Set the whole canvas to `AMBIENT_TEMPERATURE`
+    fn reset_temperature(&self) {
+        let mut command_buffer_builder = self.command_buffer_builder();
+        for buffer in [&self.temperature_in, &self.temperature_out] {
+            command_buffer_builder
+                .fill_buffer(FillBufferInfo {
+                    data: AMBIENT_TEMPERATURE.to_bits(),
+                    ..FillBufferInfo::dst_buffer(buffer.clone())
+                })
+                .unwrap();
+        }
+        self.execute(command_buffer_builder, true);
+    }
+
+    /// Append a pipeline dispatch over the whole canvas to our command buffer
     fn dispatch(
         &mut self,
-        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        builder: &mut AutoCommandBufferBuilder<SecondaryAutoCommandBuffer>,
+        pipeline: Arc<ComputePipeline>,
+        swap: bool,
+    ) {
+        let work_groups = self.config.num_work_groups();
+        self.dispatch_work_groups(builder, pipeline, IVec2::ZERO, work_groups, swap);
+    }
+
+    /// Append a pipeline dispatch of `work_groups` starting from `origin` to our command buffer
+    fn dispatch_work_groups<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
         pipeline: Arc<ComputePipeline>,
+        origin: IVec2,
+        work_groups: [u32; 2],
         swap: bool,
     ) {
         let pipeline_layout = pipeline.layout();
-        let desc_layout = pipeline_layout.set_layouts().get(0).unwrap();
-        let set = PersistentDescriptorSet::new(desc_layout.clone(), [
-            WriteDescriptorSet::buffer(0, self.matter_in.clone()),
-            WriteDescriptorSet::buffer(1, self.matter_out.clone()),
-            WriteDescriptorSet::image_view(2, self.image.clone()),
-            WriteDescriptorSet::buffer(3, self.query_matter.clone()),
-        ])
-        .unwrap();
+        let set = self.descriptor_set();
         // Assumes all shaders that are 'dispatched' have the same push constants
         let push_constants = fall_empty_cs::ty::PushConstants {
-            sim_step: self.sim_step as u32,
-            move_step: self.move_step as u32,
-            draw_pos_start: self.draw_pos_start.into(),
-            draw_pos_end: self.draw_pos_end.into(),
-            draw_radius: self.draw_radius,
-            draw_matter: self.draw_matter.value,
-            query_pos: self.query_pos.into(),
+            move_pass: self.move_pass,
+            dispersion_step: self.dispersion_step,
+            dispatch_origin: origin.into(),
+            num_strokes: self.num_strokes,
+            wake_radius: self.wake_radius,
         };
         builder
             .bind_pipeline_compute(pipeline.clone())
             .bind_descriptor_sets(PipelineBindPoint::Compute, pipeline_layout.clone(), 0, set)
             .push_constants(pipeline_layout.clone(), 0, push_constants)
-            .dispatch([NUM_WORK_GROUPS_X, NUM_WORK_GROUPS_Y, 1])
+            .dispatch([work_groups[0], work_groups[1], 1])
             .unwrap();
 
         // Double buffering: Swap input and output so the output becomes the input for next frame
         if swap {
             std::mem::swap(&mut self.matter_in, &mut self.matter_out);
+            self.orientation.matter_swapped = !self.orientation.matter_swapped;
         }
     }
+
+    /// Descriptor set of our buffers in their current orientation. All pipelines share the same layout.
+    fn descriptor_set(&mut self) -> Arc<PersistentDescriptorSet> {
+        let index = self.orientation.index();
+        if let Some(set) = &self.descriptor_sets[index] {
+            return set.clone();
+        }
+        let desc_layout = self.color_pipeline.layout().set_layouts().first().unwrap();
+        let set = PersistentDescriptorSet::new(desc_layout.clone(), [
+            WriteDescriptorSet::buffer(0, self.matter_in.clone()),
+            WriteDescriptorSet::buffer(1, self.matter_out.clone()),
+            WriteDescriptorSet::image_view(2, self.image.clone()),
+            WriteDescriptorSet::buffer(3, self.matter_definitions.clone()),
+            WriteDescriptorSet::buffer(4, self.temperature_in.clone()),
+            WriteDescriptorSet::buffer(5, self.temperature_out.clone()),
+            WriteDescriptorSet::buffer(6, self.reactions.clone()),
+            WriteDescriptorSet::buffer(7, self.tile_changed.clone()),
+            WriteDescriptorSet::buffer(8, self.tile_awake.clone()),
+            WriteDescriptorSet::buffer(9, self.strokes.clone()),
+            WriteDescriptorSet::buffer(10, self.step_state.clone()),
+        ])
+        .unwrap();
+        self.descriptor_sets[index] = Some(set.clone());
+        set
+    }
 }
 
 mod fall_empty_cs {
@@ -332,6 +1177,13 @@ mod fall_empty_cs {
     }
 }
 
+mod sink_lighter_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "compute_shaders/sink_lighter.glsl"
+    }
+}
+
 mod slide_down_empty_cs {
     vulkano_shaders::shader! {
         ty: "compute",
@@ -339,24 +1191,66 @@ mod slide_down_empty_cs {
     }
 }
 
-mod color_cs {
+mod rise_empty_cs {
     vulkano_shaders::shader! {
         ty: "compute",
-        path: "compute_shaders/color.glsl"
+        path: "compute_shaders/rise_empty.glsl"
     }
 }
 
-mod draw_matter_cs {
+mod slide_up_empty_cs {
     vulkano_shaders::shader! {
         ty: "compute",
-        path: "compute_shaders/draw_matter.glsl"
+        path: "compute_shaders/slide_up_empty.glsl"
     }
 }
 
-mod query_matter_cs {
+mod disperse_empty_cs {
     vulkano_shaders::shader! {
         ty: "compute",
-        path: "compute_shaders/query_matter.glsl"
+        path: "compute_shaders/disperse_empty.glsl"
+    }
+}
+
+mod burn_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "compute_shaders/burn.glsl"
+    }
+}
+
+mod heat_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "compute_shaders/heat.glsl"
+    }
+}
+
+mod react_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "compute_shaders/react.glsl"
+    }
+}
+
+mod wake_tiles_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "compute_shaders/wake_tiles.glsl"
+    }
+}
+
+mod color_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "compute_shaders/color.glsl"
+    }
+}
+
+mod draw_matter_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "compute_shaders/draw_matter.glsl"
     }
 }
 
@@ -365,36 +1259,340 @@ mod query_matter_cs {
 // you'll want to be doing more unit testing...
 #[cfg(test)]
 mod tests {
-    use bevy::math::IVec2;
+    use bevy::math::{IVec2, UVec2, Vec2};
     use vulkano_util::context::VulkanoContext;
 
-    use crate::{ca_simulator::CASimulator, matter::MatterId};
+    use crate::{
+        ca_simulator::{CASimulator, CASimulatorConfig},
+        grid::GridRect,
+        matter::{test_matter, MatterId, MatterRegistry},
+    };
 
-    fn test_setup() -> (VulkanoContext, CASimulator) {
+    fn test_setup(config: CASimulatorConfig) -> (VulkanoContext, CASimulator) {
         // Create vulkano context
         let vulkano_context = VulkanoContext::default();
         // Create Simulation pipeline
-        let simulator = CASimulator::new(vulkano_context.compute_queue());
+        let simulator = CASimulator::new(
+            vulkano_context.compute_queue(),
+            config,
+            MatterRegistry::default(),
+            None,
+        );
         (vulkano_context, simulator)
     }
 
     #[test]
     fn test_example_sandfall() {
-        let (_ctx, mut simulator) = test_setup();
+        let (_ctx, mut simulator) = test_setup(CASimulatorConfig::default());
         let pos = IVec2::new(10, 10);
         // Empty matter first
-        assert_eq!(simulator.query_matter(pos), Some(MatterId::Empty));
-        simulator.draw_matter(pos.as_vec2(), pos.as_vec2(), 0.5, MatterId::Sand);
+        assert_eq!(simulator.query_matter(pos), Some(MatterId::EMPTY));
+        simulator.draw_matter(pos.as_vec2(), pos.as_vec2(), 0.5, test_matter("Sand"));
         // After drawing, We have Sand
-        assert_eq!(simulator.query_matter(pos), Some(MatterId::Sand));
+        assert_eq!(simulator.query_matter(pos), Some(test_matter("Sand")));
         // Step once
         simulator.step(1, false);
         // Old position is empty
-        assert_eq!(simulator.query_matter(pos), Some(MatterId::Empty));
+        assert_eq!(simulator.query_matter(pos), Some(MatterId::EMPTY));
         // New position under has Sand
         assert_eq!(
             simulator.query_matter(pos + IVec2::new(0, -1)),
-            Some(MatterId::Sand)
+            Some(test_matter("Sand"))
+        );
+    }
+
+    #[test]
+    fn test_recorded_steps_reused() {
+        let (ctx, mut simulator) = test_setup(CASimulatorConfig::default());
+        let mut rerecorded = CASimulator::new(
+            ctx.compute_queue(),
+            CASimulatorConfig::default(),
+            MatterRegistry::default(),
+            None,
+        );
+        for (i, simulator) in [&mut simulator, &mut rerecorded].into_iter().enumerate() {
+            simulator.set_seed(1);
+            simulator.draw_matter(
+                Vec2::new(60.0, 200.0),
+                Vec2::new(60.0, 200.0),
+                8.0,
+                test_matter("Sand"),
+            );
+            simulator.draw_matter(
+                Vec2::new(90.0, 200.0),
+                Vec2::new(90.0, 200.0),
+                8.0,
+                test_matter("Water"),
+            );
+            for step in 0..60 {
+                if i == 1 {
+                    simulator.recorded_steps.clear();
+                }
+                simulator.step(1 + step % 3, step % 10 == 9);
+            }
+        }
+        // One recording per move steps & orientation
+        assert!(simulator.recorded_steps.len() <= 16);
+        assert_eq!(simulator.move_step, rerecorded.move_step);
+        assert_eq!(
+            simulator.read_grid().cells(),
+            rerecorded.read_grid().cells()
+        );
+    }
+
+    #[test]
+    fn test_settled_tiles_sleep() {
+        let (_ctx, mut simulator) = test_setup(CASimulatorConfig {
+            canvas_size_x: 256,
+            canvas_size_y: 256,
+            ..CASimulatorConfig::default()
+        });
+        let sand = test_matter("Sand");
+        let count = |simulator: &CASimulator| {
+            simulator
+                .read_grid()
+                .cells()
+                .iter()
+                .filter(|m| m.matter_id() == sand)
+                .count()
+        };
+        simulator.draw_matter(Vec2::new(20.0, 200.0), Vec2::new(20.0, 200.0), 5.0, sand);
+        let poured = count(&simulator);
+        simulator.step(1, false);
+        assert!(simulator.num_awake_tiles() > 0);
+        for _ in 0..200 {
+            simulator.step(1, false);
+        }
+        // Settled sand is left alone, without losing any of it
+        assert_eq!(simulator.num_awake_tiles(), 0);
+        assert_eq!(count(&simulator), poured);
+        // Drawing wakes the tiles it touches
+        let pos = IVec2::new(200, 200);
+        simulator.draw_matter(pos.as_vec2(), pos.as_vec2(), 0.5, sand);
+        simulator.step(1, false);
+        assert!(simulator.num_awake_tiles() > 0);
+        assert_eq!(simulator.query_matter(pos), Some(MatterId::EMPTY));
+        assert_eq!(simulator.query_matter(pos + IVec2::new(0, -1)), Some(sand));
+    }
+
+    #[test]
+    fn test_friction_piles_sleep() {
+        let (_ctx, mut simulator) = test_setup(CASimulatorConfig {
+            canvas_size_x: 256,
+            canvas_size_y: 256,
+            ..CASimulatorConfig::default()
+        });
+        let (gravel, snow) = (test_matter("Gravel"), test_matter("Snow"));
+        let count = |simulator: &CASimulator, matter: MatterId| {
+            simulator
+                .read_grid()
+                .cells()
+                .iter()
+                .filter(|m| m.matter_id() == matter)
+                .count()
+        };
+        simulator.draw_matter(Vec2::new(60.0, 200.0), Vec2::new(60.0, 200.0), 8.0, gravel);
+        simulator.draw_matter(Vec2::new(180.0, 200.0), Vec2::new(180.0, 200.0), 8.0, snow);
+        let poured = (count(&simulator, gravel), count(&simulator, snow));
+        for _ in 0..300 {
+            simulator.step(1, false);
+        }
+        // Edges of piles held by friction don't keep their tiles awake
+        assert_eq!(simulator.num_awake_tiles(), 0);
+        assert_eq!((count(&simulator, gravel), count(&simulator, snow)), poured);
+    }
+
+    #[test]
+    fn test_queued_strokes() {
+        let (_ctx, mut simulator) = test_setup(CASimulatorConfig::default());
+        let (sand, wood) = (test_matter("Sand"), test_matter("Wood"));
+        let (pos, other_pos) = (IVec2::new(10, 10), IVec2::new(100, 50));
+        simulator.queue_stroke(pos.as_vec2(), pos.as_vec2(), 2.0, sand);
+        simulator.queue_stroke(pos.as_vec2(), pos.as_vec2(), 0.5, wood);
+        simulator.queue_stroke(other_pos.as_vec2(), other_pos.as_vec2(), 0.5, sand);
+        // Nothing is drawn until the next step
+        assert_eq!(simulator.query_matter(pos), Some(MatterId::EMPTY));
+        simulator.step(1, true);
+        // Later strokes paint over earlier ones
+        assert_eq!(simulator.query_matter(pos), Some(wood));
+        assert_eq!(simulator.query_matter(pos + IVec2::new(0, 2)), Some(sand));
+        assert_eq!(simulator.query_matter(other_pos), Some(sand));
+    }
+
+    #[test]
+    fn test_non_square_canvas() {
+        // Neither side is a multiple of the kernel size & the canvas is wider than it is tall
+        let (_ctx, mut simulator) = test_setup(CASimulatorConfig {
+            canvas_size_x: 100,
+            canvas_size_y: 37,
+            ..CASimulatorConfig::default()
+        });
+        let pos = IVec2::new(99, 36);
+        simulator.draw_matter(pos.as_vec2(), pos.as_vec2(), 0.5, test_matter("Sand"));
+        assert_eq!(simulator.query_matter(pos), Some(test_matter("Sand")));
+        // With square indexing (y * canvas_size_y + x) this cell would alias the one we drew
+        assert_eq!(
+            simulator.query_matter(IVec2::new(31, 14)),
+            Some(MatterId::EMPTY)
+        );
+        simulator.step(1, false);
+        assert_eq!(simulator.query_matter(pos), Some(MatterId::EMPTY));
+        assert_eq!(
+            simulator.query_matter(pos + IVec2::new(0, -1)),
+            Some(test_matter("Sand"))
+        );
+        // Outside the canvas
+        assert_eq!(simulator.query_matter(IVec2::new(100, 0)), None);
+    }
+
+    #[test]
+    fn test_read_region() {
+        let (_ctx, mut simulator) = test_setup(CASimulatorConfig {
+            canvas_size_x: 50,
+            canvas_size_y: 30,
+            ..CASimulatorConfig::default()
+        });
+        let pos = IVec2::new(20, 10);
+        simulator.draw_matter(pos.as_vec2(), pos.as_vec2(), 0.5, test_matter("Wood"));
+        let grid = simulator.read_grid();
+        assert_eq!((grid.width(), grid.height()), (50, 30));
+        let wood = grid
+            .cells()
+            .iter()
+            .filter(|m| m.matter_id() == test_matter("Wood"))
+            .count();
+        assert_eq!(wood, 1);
+        assert_eq!(grid.get(pos).unwrap().matter_id(), test_matter("Wood"));
+        // Region coordinates are relative to its origin
+        let region = simulator.read_region(GridRect::new(UVec2::new(18, 9), 4, 3));
+        assert_eq!(region.cells().len(), 12);
+        assert_eq!(
+            region.get(IVec2::new(2, 1)).unwrap().matter_id(),
+            test_matter("Wood")
+        );
+        assert_eq!(
+            region.get(IVec2::new(2, 2)).unwrap().matter_id(),
+            MatterId::EMPTY
+        );
+        assert_eq!(region.get(IVec2::new(4, 1)), None);
+        // Empty regions have no cells
+        let region = simulator.read_region(GridRect::new(UVec2::new(50, 10), 0, 3));
+        assert_eq!((region.width(), region.height()), (0, 3));
+        assert!(region.cells().is_empty());
+        simulator.write_region(UVec2::new(10, 30), 5, 0, &[]);
+    }
+
+    #[test]
+    fn test_write_and_fill_region() {
+        let (_ctx, mut simulator) = test_setup(CASimulatorConfig {
+            canvas_size_x: 50,
+            canvas_size_y: 30,
+            ..CASimulatorConfig::default()
+        });
+        let wood = simulator
+            .matter_registry()
+            .matter_with_color(test_matter("Wood"));
+        let sand = simulator
+            .matter_registry()
+            .matter_with_color(test_matter("Sand"));
+        let empty = simulator
+            .matter_registry()
+            .matter_with_color(MatterId::EMPTY);
+        let cells = [wood, sand, empty, sand, wood, empty];
+        simulator.write_region(UVec2::new(10, 5), 3, 2, &cells);
+        let region = simulator.read_region(GridRect::new(UVec2::new(10, 5), 3, 2));
+        assert_eq!(region.cells(), &cells[..]);
+        // Neighbors are untouched
+        assert_eq!(
+            simulator.query_matter(IVec2::new(13, 5)),
+            Some(MatterId::EMPTY)
+        );
+
+        // Only the bottom row of a sand block has empty space to fall into on the first step
+        simulator.fill_region(GridRect::new(UVec2::new(30, 20), 4, 4), test_matter("Sand"));
+        simulator.step(1, false);
+        let region = simulator.read_region(GridRect::new(UVec2::new(30, 19), 4, 5));
+        for x in 0..4 {
+            assert_eq!(region.get(IVec2::new(x, 0)), Some(sand));
+            // Sand swapped places with the (unwritten) empty cell below it
+            assert_eq!(
+                region.get(IVec2::new(x, 1)).unwrap().matter_id(),
+                MatterId::EMPTY
+            );
+            assert_eq!(region.get(IVec2::new(x, 4)), Some(sand));
+        }
+    }
+
+    #[test]
+    fn test_async_queries() {
+        let (_ctx, mut simulator) = test_setup(CASimulatorConfig {
+            canvas_size_x: 50,
+            canvas_size_y: 30,
+            ..CASimulatorConfig::default()
+        });
+        let pos = IVec2::new(20, 10);
+        simulator.draw_matter(pos.as_vec2(), pos.as_vec2(), 0.5, test_matter("Wood"));
+        // Many positions resolved in one submission, results of earlier steps kept until polled
+        for _ in 0..5 {
+            for x in 18..23 {
+                simulator.queue_query(IVec2::new(x, 10));
+            }
+            simulator.queue_query(IVec2::new(-1, 10));
+            simulator.submit_queries();
+            // Copies are recorded into the step's submission
+            simulator.step(1, false);
+        }
+        let mut results = vec![];
+        while results.len() < 30 {
+            results.extend(simulator.poll_queries());
+        }
+        assert_eq!(results.len(), 30);
+        for result in results {
+            let expected = if result.pos == pos {
+                Some(test_matter("Wood"))
+            } else if result.pos.x < 0 {
+                None
+            } else {
+                Some(MatterId::EMPTY)
+            };
+            assert_eq!(result.matter.map(|m| m.matter_id()), expected);
+        }
+    }
+
+    #[test]
+    fn test_queries_pending_while_stepping() {
+        let (_ctx, mut simulator) = test_setup(CASimulatorConfig {
+            canvas_size_x: 50,
+            canvas_size_y: 30,
+            ..CASimulatorConfig::default()
+        });
+        let pos = IVec2::new(20, 10);
+        simulator.draw_matter(pos.as_vec2(), pos.as_vec2(), 0.5, test_matter("Sand"));
+        // Copies read the grid each step leaves behind, the sand has fallen a cell after the first
+        let pos = pos + IVec2::new(0, -1);
+        // Like the gui: a query each frame, stepping & drawing before its copy is polled
+        let mut results = vec![];
+        for _ in 0..4 {
+            simulator.queue_query(pos);
+            simulator.submit_queries();
+            simulator.step(1, false);
+            simulator.draw_matter(
+                Vec2::new(5.0, 25.0),
+                Vec2::new(5.0, 25.0),
+                0.5,
+                test_matter("Sand"),
+            );
+            results.extend(simulator.poll_queries());
+        }
+        assert_eq!(results.len(), 4);
+        // The first query was copied before the sand fell further
+        assert_eq!(
+            results[0].matter.map(|m| m.matter_id()),
+            Some(test_matter("Sand"))
+        );
+        assert_eq!(
+            results[3].matter.map(|m| m.matter_id()),
+            Some(MatterId::EMPTY)
         );
     }
 }
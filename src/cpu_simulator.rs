@@ -0,0 +1,1377 @@
+use bevy::math::{IVec2, UVec2, Vec2, Vec4};
+
+use crate::{
+    grid::GridRect,
+    matter::{
+        MatterBehaviour, MatterId, MatterRegistry, MatterWithColor, LIFETIME_MASK, VELOCITY_MASK,
+        VELOCITY_SHIFT,
+    },
+    AMBIENT_TEMPERATURE,
+};
+
+/// Grid directions, same as in `dirs.glsl`
+const UP: usize = 1;
+const RIGHT: usize = 3;
+const DOWN: usize = 5;
+const LEFT: usize = 7;
+
+/// Hash salts of the passes, same as in `includes.glsl`
+const DISPERSION_SALT: u32 = 0;
+const BURN_SALT: u32 = 256;
+const REACTION_SALT: u32 = 512;
+const COLOR_SALT: u32 = 768;
+const SLIDE_SALT: u32 = 1024;
+const FRICTION_SALT: u32 = 1280;
+
+/// Fall velocity & distance, same as in `matter.glsl` & `fall_empty.glsl`
+const VELOCITY_SCALE: u32 = 16;
+const GRAVITY_ACCELERATION: u32 = 4;
+const MAX_FALL_DISTANCE: i32 = 8;
+const MAX_VELOCITY: u32 = (MAX_FALL_DISTANCE as u32 - 1) * VELOCITY_SCALE;
+
+/// Temperatures this close to ambient settle at it, same as in `heat.glsl`
+const AMBIENT_EPSILON: f32 = 0.001;
+
+/// Neighbor offsets, same as in `dirs.glsl`
+const OFFSETS: [IVec2; 8] = [
+    IVec2::new(-1, 1),
+    IVec2::new(0, 1),
+    IVec2::new(1, 1),
+    IVec2::new(1, 0),
+    IVec2::new(1, -1),
+    IVec2::new(0, -1),
+    IVec2::new(-1, -1),
+    IVec2::new(-1, 0),
+];
+
+/// A CPU reference implementation of the kernels of [`CASimulator`](crate::ca_simulator::CASimulator).
+/// Operates on the same packed [`MatterWithColor`] layout and follows the same rules, so it can be used
+/// as an oracle: step both simulators from the same state and their grids should match cell for cell.
+///
+/// It simulates every cell in every pass while the kernels skip sleeping tiles, so matching grids also show
+/// that sleeping never changes the outcome.
+///
+/// Matter ids match exactly. Drawn colors are varied & heat is diffused with the same float math as the
+/// kernels, but GPU float precision may make colors & temperatures differ slightly.
+pub struct CpuSimulator {
+    width: u32,
+    height: u32,
+    matter_in: Vec<MatterWithColor>,
+    matter_out: Vec<MatterWithColor>,
+    temperature_in: Vec<f32>,
+    temperature_out: Vec<f32>,
+    empty_matter: MatterWithColor,
+    matter_registry: MatterRegistry,
+    pub sim_step: u32,
+    move_step: u32,
+    dispersion_step: u32,
+    world_seed: u32,
+}
+
+impl CpuSimulator {
+    /// Create a new simulator with a zeroed grid, like a freshly allocated device buffer
+    pub fn new(width: u32, height: u32, matter_registry: MatterRegistry) -> CpuSimulator {
+        assert!(width > 0 && height > 0);
+        let num_cells = (width * height) as usize;
+        CpuSimulator {
+            width,
+            height,
+            matter_in: vec![MatterWithColor::from(0); num_cells],
+            matter_out: vec![MatterWithColor::from(0); num_cells],
+            temperature_in: vec![AMBIENT_TEMPERATURE; num_cells],
+            temperature_out: vec![AMBIENT_TEMPERATURE; num_cells],
+            empty_matter: matter_registry.matter_with_color(MatterId::EMPTY),
+            matter_registry,
+            sim_step: 0,
+            move_step: 0,
+            dispersion_step: 0,
+            world_seed: 0,
+        }
+    }
+
+    /// Seed the random numbers, same as [`CASimulator::set_seed`](crate::ca_simulator::CASimulator::set_seed)
+    pub fn set_seed(&mut self, seed: u32) {
+        self.world_seed = seed;
+    }
+
+    /// Canvas size in pixels
+    pub fn canvas_size(&self) -> UVec2 {
+        UVec2::new(self.width, self.height)
+    }
+
+    /// All cells, row by row starting from the bottom row (y = 0)
+    pub fn cells(&self) -> &[MatterWithColor] {
+        &self.matter_in
+    }
+
+    /// Matter at pos
+    pub fn matter(&self, pos: IVec2) -> Option<MatterWithColor> {
+        if self.is_inside(pos) {
+            Some(self.read_matter(pos))
+        } else {
+            None
+        }
+    }
+
+    /// Temperature at pos
+    pub fn temperature(&self, pos: IVec2) -> Option<f32> {
+        if self.is_inside(pos) {
+            Some(self.temperature_in[self.get_index(pos)])
+        } else {
+            None
+        }
+    }
+
+    /// Query matter at pos
+    pub fn query_matter(&self, pos: IVec2) -> Option<MatterId> {
+        self.matter(pos).map(|m| m.matter_id())
+    }
+
+    /// Draw matter line with given radius
+    pub fn draw_matter(&mut self, start: Vec2, end: Vec2, radius: f32, matter: MatterId) {
+        let matter = self.matter_registry.matter_with_color(matter);
+        let rect = match GridRect::stroke_bounds(start, end, radius, self.canvas_size()) {
+            Some(rect) => rect,
+            None => return,
+        };
+        let (origin, end_pos) = (rect.origin.as_ivec2(), rect.end().as_ivec2());
+        for y in origin.y..end_pos.y {
+            for x in origin.x..end_pos.x {
+                let pos = IVec2::new(x, y);
+                let point_on_line = closest_point_on_line(start, end, pos.as_vec2());
+                self.draw_matter_circle(pos, point_on_line.as_ivec2(), radius, matter);
+            }
+        }
+    }
+
+    /// Step simulation
+    pub fn step(&mut self, move_steps: u32, is_paused: bool) {
+        if !is_paused {
+            for _ in 0..move_steps {
+                self.step_movement(Self::fall_empty);
+                self.step_movement(Self::sink_lighter);
+                self.step_movement(Self::sink_lighter);
+                self.step_movement(Self::slide_down_empty);
+                self.step_movement(Self::rise_empty);
+                self.step_movement(Self::slide_up_empty);
+                for dispersion_step in 0..self.matter_registry.max_dispersion() {
+                    self.dispersion_step = dispersion_step;
+                    self.run_kernel(Self::disperse_empty);
+                }
+                self.run_kernel(Self::burn);
+                self.run_kernel(Self::react);
+            }
+            self.step_heat();
+        }
+        self.sim_step += 1;
+    }
+
+    /// Run a movement kernel over the grid, then swap buffers. Each movement pass gets its own random
+    /// numbers & pairings through move_step
+    fn step_movement(&mut self, kernel: fn(&Self, IVec2) -> MatterWithColor) {
+        self.run_kernel(kernel);
+        self.move_step += 1;
+    }
+
+    /// Diffuse heat & change matter at its threshold temperatures, then swap both matter & temperature
+    fn step_heat(&mut self) {
+        for y in 0..self.height as i32 {
+            for x in 0..self.width as i32 {
+                let pos = IVec2::new(x, y);
+                let index = self.get_index(pos);
+                let (matter, temperature) = self.heat(pos);
+                self.matter_out[index] = matter;
+                self.temperature_out[index] = temperature;
+            }
+        }
+        std::mem::swap(&mut self.matter_in, &mut self.matter_out);
+        std::mem::swap(&mut self.temperature_in, &mut self.temperature_out);
+    }
+
+    /// Run a kernel over the grid, then swap buffers
+    fn run_kernel(&mut self, kernel: fn(&Self, IVec2) -> MatterWithColor) {
+        for y in 0..self.height as i32 {
+            for x in 0..self.width as i32 {
+                let pos = IVec2::new(x, y);
+                let index = self.get_index(pos);
+                self.matter_out[index] = kernel(self, pos);
+            }
+        }
+        std::mem::swap(&mut self.matter_in, &mut self.matter_out);
+    }
+
+    fn is_fall_blocked(&self, pos: IVec2) -> bool {
+        !self.is_inside(pos) || !is_empty(self.read_matter(pos))
+    }
+
+    /// Velocity of falling matter stopped by the cell at blocker_pos, at most the velocity of falling
+    /// matter
+    fn blocked_velocity(&self, matter: MatterWithColor, blocker_pos: IVec2) -> u32 {
+        if !self.is_inside(blocker_pos) {
+            return 0;
+        }
+        let blocker = self.read_matter(blocker_pos);
+        if self.has_behaviour(blocker, MatterBehaviour::Gravity) {
+            matter.velocity().min(blocker.velocity())
+        } else {
+            0
+        }
+    }
+
+    /// Falling matter that moved `moved` cells to pos, accelerates if nothing stopped it
+    fn fallen(&self, matter: MatterWithColor, moved: i32, pos: IVec2) -> MatterWithColor {
+        let velocity = if moved == fall_distance(matter) {
+            (matter.velocity() + GRAVITY_ACCELERATION).min(MAX_VELOCITY)
+        } else {
+            self.blocked_velocity(matter, pos + IVec2::new(0, -1))
+        };
+        with_velocity(matter, velocity)
+    }
+
+    fn fall_empty(&self, pos: IVec2) -> MatterWithColor {
+        let current = self.read_matter(pos);
+        if is_empty(current) {
+            // Take the first cell above if it lands here
+            for moved in 1..=MAX_FALL_DISTANCE {
+                let from_pos = pos + IVec2::new(0, moved);
+                if !self.is_inside(from_pos) {
+                    break;
+                }
+                let from = self.read_matter(from_pos);
+                if !is_empty(from) {
+                    let distance = fall_distance(from);
+                    let lands_here = distance == moved
+                        || (distance > moved && self.is_fall_blocked(pos + IVec2::new(0, -1)));
+                    if self.has_behaviour(from, MatterBehaviour::Gravity) && lands_here {
+                        return self.fallen(from, moved, pos);
+                    }
+                    break;
+                }
+            }
+        } else if self.has_behaviour(current, MatterBehaviour::Gravity) {
+            // Leave for the last empty cell within fall distance, swapping places with it
+            let distance = fall_distance(current);
+            let mut moved = 0;
+            while moved < distance && !self.is_fall_blocked(pos + IVec2::new(0, -moved - 1)) {
+                moved += 1;
+            }
+            return if moved == 0 {
+                with_velocity(
+                    current,
+                    self.blocked_velocity(current, pos + IVec2::new(0, -1)),
+                )
+            } else {
+                self.read_matter(pos + IVec2::new(0, -moved))
+            };
+        }
+        current
+    }
+
+    fn sink_lighter(&self, pos: IVec2) -> MatterWithColor {
+        let current = self.read_matter(pos);
+        let is_pair_bottom = (pos.y as u32 + self.sim_step + self.move_step) % 2 == 0;
+        if is_pair_bottom {
+            let up = self.get_neighbor(pos, UP);
+            if !self.is_at_border_top(pos) && self.sinks_through(up, current) {
+                return up;
+            }
+        } else {
+            let down = self.get_neighbor(pos, DOWN);
+            if !self.is_at_border_bottom(pos) && self.sinks_through(current, down) {
+                return down;
+            }
+        }
+        current
+    }
+
+    fn rise_empty(&self, pos: IVec2) -> MatterWithColor {
+        let current = self.read_matter(pos);
+        let down = self.get_neighbor(pos, DOWN);
+        let up = self.get_neighbor(pos, UP);
+        let m = if !self.is_at_border_bottom(pos) && self.rises_on_empty(down, current) {
+            down
+        } else if !self.is_at_border_top(pos) && self.rises_on_empty(current, up) {
+            up
+        } else {
+            current
+        };
+        self.age_gas(m)
+    }
+
+    fn slide_down_empty(&self, pos: IVec2) -> MatterWithColor {
+        self.slide_empty(pos, -1)
+    }
+
+    fn slide_up_empty(&self, pos: IVec2) -> MatterWithColor {
+        self.slide_empty(pos, 1)
+    }
+
+    fn can_slide_to(
+        &self,
+        to_pos: IVec2,
+        from: MatterWithColor,
+        from_blocker: MatterWithColor,
+        dy: i32,
+    ) -> bool {
+        if !self.is_inside(to_pos) {
+            return false;
+        }
+        let to = self.read_matter(to_pos);
+        if dy < 0 {
+            self.slides_on_empty(from, to, from_blocker)
+        } else {
+            self.slides_up_on_empty(from, to, from_blocker)
+        }
+    }
+
+    /// Direction the cell at pos would slide in without friction: -1 left, 1 right, 0 none
+    fn free_slide_dir(&self, pos: IVec2, dy: i32) -> i32 {
+        let blocker_pos = pos + IVec2::new(0, dy);
+        if !self.is_inside(pos) || !self.is_inside(blocker_pos) {
+            return 0;
+        }
+        let m = self.read_matter(pos);
+        let blocker = self.read_matter(blocker_pos);
+        let left = self.can_slide_to(pos + IVec2::new(-1, dy), m, blocker, dy);
+        let right = self.can_slide_to(pos + IVec2::new(1, dy), m, blocker, dy);
+        match (left, right) {
+            (true, true) if self.hash(pos, SLIDE_SALT) & 1 == 0 => -1,
+            (true, true) => 1,
+            (true, false) => -1,
+            (false, true) => 1,
+            (false, false) => 0,
+        }
+    }
+
+    /// Direction the cell at pos slides in: -1 left, 1 right, 0 none
+    fn slide_dir(&self, pos: IVec2, dy: i32) -> i32 {
+        let dir = self.free_slide_dir(pos, dy);
+        if dir != 0 && self.holds_by_friction(self.read_matter(pos), pos) {
+            0
+        } else {
+            dir
+        }
+    }
+
+    /// Side the cell sliding into empty pos comes from: -1 left, 1 right, 0 none
+    fn slide_source_dir(&self, pos: IVec2, dy: i32) -> i32 {
+        let from_left = self.slide_dir(pos + IVec2::new(-1, -dy), dy) == 1;
+        let from_right = self.slide_dir(pos + IVec2::new(1, -dy), dy) == -1;
+        match (from_left, from_right) {
+            (true, true) if self.hash(pos, SLIDE_SALT + 1) & 1 == 0 => -1,
+            (true, true) => 1,
+            (true, false) => -1,
+            (false, true) => 1,
+            (false, false) => 0,
+        }
+    }
+
+    fn slide_empty(&self, pos: IVec2, dy: i32) -> MatterWithColor {
+        let current = self.read_matter(pos);
+        if is_empty(current) {
+            let source_dir = self.slide_source_dir(pos, dy);
+            if source_dir != 0 {
+                return self.read_matter(pos + IVec2::new(source_dir, -dy));
+            }
+        } else {
+            let dir = self.slide_dir(pos, dy);
+            let to_pos = pos + IVec2::new(dir, dy);
+            if dir != 0 && self.slide_source_dir(to_pos, dy) == -dir {
+                return self.read_matter(to_pos);
+            }
+        }
+        current
+    }
+
+    fn disperse_empty_dir(&self, pos: IVec2, from_dir: usize, to_dir: usize) -> MatterWithColor {
+        let current = self.read_matter(pos);
+        let from_pos = pos + OFFSETS[from_dir];
+        let to_pos = pos + OFFSETS[to_dir];
+        let from = self.get_neighbor(pos, from_dir);
+        let to = self.get_neighbor(pos, to_dir);
+        if self.is_inside(from_pos)
+            && (self.disperses_on_empty(from, current, from_pos)
+                || self.drifts_on_empty(from, current, from_pos))
+        {
+            from
+        } else if self.is_inside(to_pos)
+            && (self.disperses_on_empty(current, to, pos) || self.drifts_on_empty(current, to, pos))
+        {
+            to
+        } else {
+            current
+        }
+    }
+
+    fn disperse_empty(&self, pos: IVec2) -> MatterWithColor {
+        if (self.sim_step + self.move_step) % 2 == 0 {
+            self.disperse_empty_dir(pos, RIGHT, LEFT)
+        } else {
+            self.disperse_empty_dir(pos, LEFT, RIGHT)
+        }
+    }
+
+    fn burn_down(&self, matter: MatterWithColor) -> MatterWithColor {
+        let lifetime = self
+            .matter_registry
+            .get(matter.matter_id())
+            .map(|d| d.lifetime)
+            .unwrap_or(0);
+        if lifetime == 0 {
+            matter
+        } else if matter.lifetime() > 1 {
+            with_lifetime(matter, matter.lifetime() - 1)
+        } else {
+            self.matter_registry.burns_into(matter.matter_id())
+        }
+    }
+
+    fn burn(&self, pos: IVec2) -> MatterWithColor {
+        let current = self.read_matter(pos);
+        let flammability = self
+            .matter_registry
+            .get(current.matter_id())
+            .map(|d| d.flammability)
+            .unwrap_or(0.0);
+        if self.has_behaviour(current, MatterBehaviour::Burning) {
+            return self.burn_down(current);
+        } else if flammability > 0.0 {
+            for dir in 0..8 {
+                let neighbor = self.get_neighbor(pos, dir);
+                if self.has_behaviour(neighbor, MatterBehaviour::Burning)
+                    && self.random(pos, BURN_SALT + dir as u32) < flammability
+                {
+                    let lifetime = self
+                        .matter_registry
+                        .get(neighbor.matter_id())
+                        .map(|d| d.lifetime)
+                        .unwrap_or(0);
+                    return with_lifetime(neighbor, lifetime);
+                }
+            }
+        }
+        current
+    }
+
+    fn react(&self, pos: IVec2) -> MatterWithColor {
+        let orientation = (self.sim_step + self.move_step) % 4;
+        let (axis, axis_pos) = if orientation % 2 == 0 {
+            (IVec2::new(0, 1), pos.y)
+        } else {
+            (IVec2::new(1, 0), pos.x)
+        };
+        let is_pair_first = (axis_pos + (orientation / 2) as i32) % 2 == 0;
+        let partner_pos = if is_pair_first {
+            pos + axis
+        } else {
+            pos - axis
+        };
+        let pair_pos = if is_pair_first { pos } else { partner_pos };
+
+        let current = self.read_matter(pos);
+        if self.is_inside(partner_pos) {
+            let partner = self.read_matter(partner_pos);
+            let reaction = self
+                .matter_registry
+                .reaction(current.matter_id(), partner.matter_id());
+            if reaction.probability > 0.0
+                && self.random(pair_pos, REACTION_SALT) < reaction.probability
+            {
+                let into = MatterWithColor::from(reaction.into);
+                return self.matter_registry.with_initial_state(into);
+            }
+        }
+        current
+    }
+
+    fn heat(&self, pos: IVec2) -> (MatterWithColor, f32) {
+        let current = self.read_matter(pos);
+        let definition = self.matter_registry.get(current.matter_id());
+        let conductivity = definition.map(|d| d.conductivity).unwrap_or(0.0);
+        let mut temperature = self.temperature_in[self.get_index(pos)];
+        let mut flow = 0.0;
+        for dir in [UP, RIGHT, DOWN, LEFT] {
+            let neighbor_pos = pos + OFFSETS[dir];
+            if self.is_inside(neighbor_pos) {
+                let neighbor_conductivity = self
+                    .matter_registry
+                    .get(self.read_matter(neighbor_pos).matter_id())
+                    .map(|d| d.conductivity)
+                    .unwrap_or(0.0);
+                flow += conductivity.min(neighbor_conductivity)
+                    * (self.temperature_in[self.get_index(neighbor_pos)] - temperature);
+            }
+        }
+        temperature += 0.25 * flow;
+        if let Some(source_temperature) = definition.and_then(|d| d.temperature) {
+            temperature = source_temperature;
+        }
+        if (temperature - AMBIENT_TEMPERATURE).abs() < AMBIENT_EPSILON {
+            temperature = AMBIENT_TEMPERATURE;
+        }
+        let definition = match definition {
+            Some(definition) => definition,
+            None => return (current, temperature),
+        };
+        let matter = match (&definition.above, &definition.below) {
+            (Some(above), _) if temperature > above.temperature => {
+                self.matter_registry.named_matter(Some(&above.into))
+            }
+            (_, Some(below)) if temperature < below.temperature => {
+                self.matter_registry.named_matter(Some(&below.into))
+            }
+            _ => current,
+        };
+        (matter, temperature)
+    }
+
+    fn draw_matter_circle(
+        &mut self,
+        pos: IVec2,
+        draw_pos: IVec2,
+        radius: f32,
+        mut matter: MatterWithColor,
+    ) {
+        let y_start = draw_pos.y - radius as i32;
+        let y_end = draw_pos.y + radius as i32;
+        let x_start = draw_pos.x - radius as i32;
+        let x_end = draw_pos.x + radius as i32;
+        if pos.x >= x_start && pos.x <= x_end && pos.y >= y_start && pos.y <= y_end {
+            let dist = (pos.as_vec2() - draw_pos.as_vec2()).length();
+            if dist.round() <= radius {
+                let color_variation = self
+                    .matter_registry
+                    .get(matter.matter_id())
+                    .map(|d| d.color_variation)
+                    .unwrap_or(0.0);
+                if color_variation > 0.0 {
+                    matter = MatterWithColor {
+                        value: (variate_color(
+                            matter.value >> 8,
+                            color_variation,
+                            self.random(pos, COLOR_SALT),
+                        ) << 8)
+                            | (matter.value & 255),
+                        ..matter
+                    };
+                }
+                let index = self.get_index(pos);
+                self.matter_in[index] = matter;
+            }
+        }
+    }
+
+    fn get_index(&self, pos: IVec2) -> usize {
+        (pos.y * self.width as i32 + pos.x) as usize
+    }
+
+    fn is_inside(&self, pos: IVec2) -> bool {
+        pos.x >= 0 && pos.x < self.width as i32 && pos.y >= 0 && pos.y < self.height as i32
+    }
+
+    fn is_at_border_top(&self, pos: IVec2) -> bool {
+        pos.y == self.height as i32 - 1
+    }
+
+    fn is_at_border_bottom(&self, pos: IVec2) -> bool {
+        pos.y == 0
+    }
+
+    fn read_matter(&self, pos: IVec2) -> MatterWithColor {
+        self.matter_in[self.get_index(pos)]
+    }
+
+    fn has_behaviour(&self, matter: MatterWithColor, behaviour: MatterBehaviour) -> bool {
+        self.matter_registry.has(matter.matter_id(), behaviour)
+    }
+
+    /// Friction is rolled per position rather than per step, same as `holds_by_friction` in
+    /// `includes.glsl`
+    fn holds_by_friction(&self, matter: MatterWithColor, pos: IVec2) -> bool {
+        let friction = self
+            .matter_registry
+            .get(matter.matter_id())
+            .map(|d| d.friction)
+            .unwrap_or(0.0);
+        self.position_random(pos, FRICTION_SALT) < friction
+    }
+
+    fn slides_on_empty(
+        &self,
+        from_diagonal: MatterWithColor,
+        to_diagonal: MatterWithColor,
+        from_down: MatterWithColor,
+    ) -> bool {
+        self.has_behaviour(from_diagonal, MatterBehaviour::Slides)
+            && !is_empty(from_down)
+            && is_empty(to_diagonal)
+    }
+
+    fn density(&self, matter: MatterWithColor) -> f32 {
+        self.matter_registry
+            .get(matter.matter_id())
+            .map(|d| d.density)
+            .unwrap_or(0.0)
+    }
+
+    fn sinks_through(&self, from: MatterWithColor, to: MatterWithColor) -> bool {
+        self.has_behaviour(from, MatterBehaviour::Gravity)
+            && (self.has_behaviour(to, MatterBehaviour::Liquid)
+                || self.has_behaviour(to, MatterBehaviour::Gas))
+            && !self.has_behaviour(to, MatterBehaviour::Static)
+            && self.density(from) > self.density(to)
+    }
+
+    fn rises_on_empty(&self, from: MatterWithColor, to: MatterWithColor) -> bool {
+        self.has_behaviour(from, MatterBehaviour::Gas) && is_empty(to)
+    }
+
+    fn slides_up_on_empty(
+        &self,
+        from_diagonal: MatterWithColor,
+        to_diagonal: MatterWithColor,
+        from_up: MatterWithColor,
+    ) -> bool {
+        self.has_behaviour(from_diagonal, MatterBehaviour::Gas)
+            && !is_empty(from_up)
+            && is_empty(to_diagonal)
+    }
+
+    /// Random integer for a position in the current pass, same as `hash` in `includes.glsl`
+    fn hash(&self, pos: IVec2, salt: u32) -> u32 {
+        let mut h = pcg(self.world_seed);
+        h = pcg(h ^ self.sim_step);
+        h = pcg(h ^ self.move_step);
+        h = pcg(h ^ salt);
+        h = pcg(h ^ pos.x as u32);
+        pcg(h ^ pos.y as u32)
+    }
+
+    /// Random number in [0, 1) from `hash`, same as `random` in `includes.glsl`
+    fn random(&self, pos: IVec2, salt: u32) -> f32 {
+        (self.hash(pos, salt) >> 8) as f32 / 16777216.0
+    }
+
+    /// Random number in [0, 1) that stays the same from step to step, same as `position_random` in
+    /// `includes.glsl`
+    fn position_random(&self, pos: IVec2, salt: u32) -> f32 {
+        let mut h = pcg(self.world_seed);
+        h = pcg(h ^ salt);
+        h = pcg(h ^ pos.x as u32);
+        (pcg(h ^ pos.y as u32) >> 8) as f32 / 16777216.0
+    }
+
+    fn drifts_on_empty(&self, from: MatterWithColor, to: MatterWithColor, from_pos: IVec2) -> bool {
+        let dispersion = self
+            .matter_registry
+            .get(from.matter_id())
+            .map(|d| d.dispersion)
+            .unwrap_or(0);
+        self.has_behaviour(from, MatterBehaviour::Gas)
+            && self.dispersion_step < dispersion
+            && is_empty(to)
+            && self.hash(from_pos, DISPERSION_SALT + self.dispersion_step) & 1 == 0
+    }
+
+    fn age_gas(&self, matter: MatterWithColor) -> MatterWithColor {
+        let lifetime = self
+            .matter_registry
+            .get(matter.matter_id())
+            .map(|d| d.lifetime)
+            .unwrap_or(0);
+        if !self.has_behaviour(matter, MatterBehaviour::Gas) || lifetime == 0 {
+            matter
+        } else if matter.lifetime() <= 1 {
+            self.empty_matter
+        } else {
+            with_lifetime(matter, matter.lifetime() - 1)
+        }
+    }
+
+    fn is_supported(&self, pos: IVec2) -> bool {
+        self.is_at_border_bottom(pos) || !is_empty(self.get_neighbor(pos, DOWN))
+    }
+
+    fn disperses_on_empty(
+        &self,
+        from: MatterWithColor,
+        to: MatterWithColor,
+        from_pos: IVec2,
+    ) -> bool {
+        let dispersion = self
+            .matter_registry
+            .get(from.matter_id())
+            .map(|d| d.dispersion)
+            .unwrap_or(0);
+        self.has_behaviour(from, MatterBehaviour::Liquid)
+            && self.dispersion_step < dispersion
+            && is_empty(to)
+            && self.is_supported(from_pos)
+    }
+
+    fn get_neighbor(&self, pos: IVec2, dir: usize) -> MatterWithColor {
+        let neighbor_pos = pos + OFFSETS[dir];
+        if self.is_inside(neighbor_pos) {
+            self.read_matter(neighbor_pos)
+        } else {
+            self.empty_matter
+        }
+    }
+}
+
+fn is_empty(matter: MatterWithColor) -> bool {
+    matter.matter_id() == MatterId::EMPTY
+}
+
+fn with_lifetime(matter: MatterWithColor, lifetime: u32) -> MatterWithColor {
+    MatterWithColor {
+        state: (matter.state & !LIFETIME_MASK) | (lifetime & LIFETIME_MASK),
+        ..matter
+    }
+}
+
+fn with_velocity(matter: MatterWithColor, velocity: u32) -> MatterWithColor {
+    MatterWithColor {
+        state: (matter.state & !(VELOCITY_MASK << VELOCITY_SHIFT))
+            | ((velocity & VELOCITY_MASK) << VELOCITY_SHIFT),
+        ..matter
+    }
+}
+
+fn fall_distance(matter: MatterWithColor) -> i32 {
+    1 + (matter.velocity() / VELOCITY_SCALE) as i32
+}
+
+// Line v->w, point p
+fn closest_point_on_line(v: Vec2, w: Vec2, p: Vec2) -> Vec2 {
+    let c = v - w;
+    // length squared
+    let l2 = c.dot(c);
+    if l2 == 0.0 {
+        return v;
+    }
+    let t = 1.0f32.min((p - v).dot(w - v) / l2).max(0.0);
+    v + t * (w - v)
+}
+
+/// PCG hash, same as `pcg` in `includes.glsl`
+fn pcg(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+fn matter_color_to_vec4(color: u32) -> Vec4 {
+    Vec4::new(
+        ((color >> 16) & 255) as f32 / 255.0,
+        ((color >> 8) & 255) as f32 / 255.0,
+        (color & 255) as f32 / 255.0,
+        1.0,
+    )
+}
+
+fn variate_color(color: u32, color_variation: f32, p: f32) -> u32 {
+    let mut color = matter_color_to_vec4(color);
+    let variation = -color_variation + 2.0 * color_variation * p;
+    color += Vec4::new(variation, variation, variation, 0.0);
+    // Clamped, light colors would wrap around to dark ones
+    let color = color.clamp(Vec4::ZERO, Vec4::ONE);
+    (((color.x * 255.0) as u32 & 255) << 16)
+        | (((color.y * 255.0) as u32 & 255) << 8)
+        | ((color.z * 255.0) as u32 & 255)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{IVec2, UVec2, Vec2};
+    use vulkano_util::context::VulkanoContext;
+
+    use crate::{
+        ca_simulator::{CASimulator, CASimulatorConfig},
+        cpu_simulator::{variate_color, CpuSimulator, MAX_FALL_DISTANCE},
+        matter::{test_matter, MatterId, MatterRegistry, MatterWithColor},
+        AMBIENT_TEMPERATURE,
+    };
+
+    /// Sand poured on a wood ledge, sliding off both of its edges
+    fn draw_test_scene(draw: &mut dyn FnMut(Vec2, Vec2, f32, MatterId)) {
+        draw(
+            Vec2::new(8.0, 6.0),
+            Vec2::new(30.0, 6.0),
+            1.0,
+            test_matter("Wood"),
+        );
+        draw(
+            Vec2::new(14.0, 18.0),
+            Vec2::new(22.0, 20.0),
+            3.0,
+            test_matter("Sand"),
+        );
+        draw(
+            Vec2::new(36.0, 2.0),
+            Vec2::new(36.0, 2.0),
+            1.5,
+            test_matter("Sand"),
+        );
+    }
+
+    #[test]
+    fn test_cpu_sandfall() {
+        let mut simulator = CpuSimulator::new(20, 20, MatterRegistry::default());
+        let pos = IVec2::new(10, 10);
+        assert_eq!(simulator.query_matter(pos), Some(MatterId::EMPTY));
+        simulator.draw_matter(pos.as_vec2(), pos.as_vec2(), 0.5, test_matter("Sand"));
+        assert_eq!(simulator.query_matter(pos), Some(test_matter("Sand")));
+        simulator.step(1, false);
+        assert_eq!(simulator.query_matter(pos), Some(MatterId::EMPTY));
+        assert_eq!(
+            simulator.query_matter(pos + IVec2::new(0, -1)),
+            Some(test_matter("Sand"))
+        );
+    }
+
+    #[test]
+    fn test_cpu_falling_accelerates() {
+        let mut simulator = CpuSimulator::new(5, 200, MatterRegistry::default());
+        let sand = test_matter("Sand");
+        let start = IVec2::new(2, 198);
+        simulator.draw_matter(start.as_vec2(), start.as_vec2(), 0.5, sand);
+        let sand_y = |simulator: &CpuSimulator| {
+            (0..200)
+                .find(|&y| simulator.query_matter(IVec2::new(2, y)) == Some(sand))
+                .unwrap()
+        };
+        // Falls one cell per step at first, then further & further
+        let mut y = start.y;
+        let mut distances = vec![];
+        while y > 0 {
+            simulator.step(1, false);
+            let next_y = sand_y(&simulator);
+            distances.push(y - next_y);
+            y = next_y;
+        }
+        assert_eq!(distances[0], 1);
+        assert!(distances
+            .windows(2)
+            .take(distances.len() - 2)
+            .all(|d| d[0] <= d[1]));
+        assert_eq!(distances.iter().max(), Some(&MAX_FALL_DISTANCE));
+        assert!(distances.len() < 50);
+        // Landing stops it
+        simulator.step(1, false);
+        assert_eq!(sand_y(&simulator), 0);
+        assert_eq!(simulator.matter(IVec2::new(2, 0)).unwrap().velocity(), 0);
+    }
+
+    #[test]
+    fn test_cpu_falling_column() {
+        let mut simulator = CpuSimulator::new(1, 300, MatterRegistry::default());
+        let sand = test_matter("Sand");
+        let sand_ys = |simulator: &CpuSimulator| {
+            (0..300)
+                .filter(|&y| simulator.query_matter(IVec2::new(0, y)) == Some(sand))
+                .collect::<Vec<_>>()
+        };
+        let velocity = |simulator: &CpuSimulator, y: i32| {
+            simulator.matter(IVec2::new(0, y)).unwrap().velocity()
+        };
+        let top = Vec2::new(0.0, 299.0);
+        simulator.draw_matter(top, top, 0.5, sand);
+        for _ in 0..20 {
+            simulator.step(1, false);
+        }
+        // A cell starting to fall below the falling one, which catches up & is stopped by it
+        let below = Vec2::new(0.0, sand_ys(&simulator)[0] as f32 - 30.0);
+        simulator.draw_matter(below, below, 0.5, sand);
+        // Stopped by falling matter, it keeps falling right behind it instead of starting over
+        let mut ys = sand_ys(&simulator);
+        while ys != [0, 1] {
+            simulator.step(1, false);
+            ys = sand_ys(&simulator);
+            if ys[1] > 1 {
+                assert!(velocity(&simulator, ys[1]) > 0);
+            }
+        }
+        assert_eq!(velocity(&simulator, 1), 0);
+    }
+
+    #[test]
+    fn test_cpu_sand_conserved_and_settles() {
+        let mut simulator = CpuSimulator::new(40, 24, MatterRegistry::default());
+        draw_test_scene(&mut |start, end, radius, matter| {
+            simulator.draw_matter(start, end, radius, matter)
+        });
+        let count = |simulator: &CpuSimulator, matter: MatterId| {
+            simulator
+                .cells()
+                .iter()
+                .filter(|m| m.matter_id() == matter)
+                .count()
+        };
+        let sand = count(&simulator, test_matter("Sand"));
+        let wood = count(&simulator, test_matter("Wood"));
+        for _ in 0..100 {
+            simulator.step(1, false);
+            assert_eq!(count(&simulator, test_matter("Sand")), sand);
+            assert_eq!(count(&simulator, test_matter("Wood")), wood);
+        }
+        // Settled: nothing moves anymore
+        let settled = simulator.cells().to_vec();
+        simulator.step(2, false);
+        assert_eq!(simulator.cells(), &settled[..]);
+    }
+
+    #[test]
+    fn test_cpu_symmetric_pile() {
+        let (width, height) = (61, 40);
+        let center = width as i32 / 2;
+        let mut simulator = CpuSimulator::new(width, height, MatterRegistry::default());
+        let sand = test_matter("Sand");
+        let count = |simulator: &CpuSimulator| {
+            simulator
+                .cells()
+                .iter()
+                .filter(|m| m.matter_id() == sand)
+                .count()
+        };
+        // Pour a thin stream at the center
+        let mut poured = 0;
+        for _ in 0..400 {
+            let pos = Vec2::new(center as f32, height as f32 - 2.0);
+            if simulator.query_matter(pos.as_ivec2()) == Some(MatterId::EMPTY) {
+                simulator.draw_matter(pos, pos, 0.5, sand);
+                poured += 1;
+            }
+            simulator.step(1, false);
+            assert_eq!(count(&simulator), poured);
+        }
+        let column_height = |x: i32| {
+            (0..height as i32)
+                .filter(|&y| simulator.query_matter(IVec2::new(x, y)) == Some(sand))
+                .count() as i32
+        };
+        // No side is favored: the pile is centered & its sides mirror each other
+        let mass_center =
+            (0..width as i32).map(|x| x * column_height(x)).sum::<i32>() as f32 / poured as f32;
+        assert!((mass_center - center as f32).abs() < 1.0);
+        for offset in 1..=center {
+            let (left, right) = (
+                column_height(center - offset),
+                column_height(center + offset),
+            );
+            assert!(
+                (left - right).abs() <= 2,
+                "column {}: {} vs {}",
+                offset,
+                left,
+                right
+            );
+        }
+    }
+
+    #[test]
+    fn test_cpu_friction_piles_steeper() {
+        let (width, height) = (61, 40);
+        let center = width as i32 / 2;
+        // Pour the same amount of matter at the center & measure the height of the settled pile
+        let pile_height = |name: &str| {
+            let mut simulator = CpuSimulator::new(width, height, MatterRegistry::default());
+            let matter = test_matter(name);
+            let mut poured = 0;
+            while poured < 150 {
+                let pos = Vec2::new(center as f32, height as f32 - 2.0);
+                if simulator.query_matter(pos.as_ivec2()) == Some(MatterId::EMPTY) {
+                    simulator.draw_matter(pos, pos, 0.5, matter);
+                    poured += 1;
+                }
+                simulator.step(1, false);
+            }
+            for _ in 0..200 {
+                simulator.step(1, false);
+            }
+            // Settled: held cells stay held
+            let settled = simulator.cells().to_vec();
+            simulator.step(20, false);
+            assert_eq!(simulator.cells(), &settled[..], "{} didn't settle", name);
+            (0..height as i32)
+                .filter(|&y| simulator.query_matter(IVec2::new(center, y)) == Some(matter))
+                .count()
+        };
+        let (sand, gravel, snow) = (
+            pile_height("Sand"),
+            pile_height("Gravel"),
+            pile_height("Snow"),
+        );
+        assert!(sand < gravel, "sand {} vs gravel {}", sand, gravel);
+        assert!(gravel < snow, "gravel {} vs snow {}", gravel, snow);
+    }
+
+    #[test]
+    fn test_cpu_water_spreads() {
+        let (width, height) = (30, 12);
+        let mut simulator = CpuSimulator::new(width, height, MatterRegistry::default());
+        let water = test_matter("Water");
+        simulator.draw_matter(Vec2::new(15.0, 8.0), Vec2::new(15.0, 8.0), 3.0, water);
+        let count = simulator
+            .cells()
+            .iter()
+            .filter(|m| m.matter_id() == water)
+            .count();
+        for _ in 0..200 {
+            simulator.step(1, false);
+        }
+        let water_cells = simulator
+            .cells()
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.matter_id() == water)
+            .map(|(i, _)| i as u32 / width)
+            .collect::<Vec<_>>();
+        assert_eq!(water_cells.len(), count);
+        // Spread into a layer on the floor instead of a pile
+        let layers = (count as u32 + width - 1) / width;
+        assert!(water_cells.iter().all(|&y| y < layers));
+    }
+
+    #[test]
+    fn test_cpu_heavier_sinks_through_lighter() {
+        let (width, height) = (20, 16);
+        let mut simulator = CpuSimulator::new(width, height, MatterRegistry::default());
+        // Three rows of each, lightest at the bottom
+        let layers = [
+            test_matter("Oil"),
+            test_matter("Water"),
+            test_matter("Sand"),
+        ];
+        for (i, &matter) in layers.iter().enumerate() {
+            let y = 1.0 + 3.0 * i as f32;
+            simulator.draw_matter(
+                Vec2::new(0.0, y),
+                Vec2::new(width as f32 - 1.0, y),
+                1.0,
+                matter,
+            );
+        }
+        for _ in 0..100 {
+            simulator.step(1, false);
+        }
+        // Heaviest at the bottom
+        for (i, cell) in simulator.cells().iter().enumerate() {
+            let y = i as u32 / width;
+            let expected = match y {
+                0..=2 => layers[2],
+                3..=5 => layers[1],
+                6..=8 => layers[0],
+                _ => MatterId::EMPTY,
+            };
+            assert_eq!(cell.matter_id(), expected, "cell {}", i);
+        }
+    }
+
+    #[test]
+    fn test_cpu_fire_burns_wood() {
+        let (width, height) = (30, 30);
+        let mut simulator = CpuSimulator::new(width, height, MatterRegistry::default());
+        let (wood, fire, smoke) = (
+            test_matter("Wood"),
+            test_matter("Fire"),
+            test_matter("Smoke"),
+        );
+        simulator.draw_matter(Vec2::new(5.0, 10.0), Vec2::new(24.0, 10.0), 1.0, wood);
+        simulator.draw_matter(Vec2::new(5.0, 12.0), Vec2::new(5.0, 12.0), 0.5, fire);
+        let count = |simulator: &CpuSimulator, matter: MatterId| {
+            simulator
+                .cells()
+                .iter()
+                .filter(|m| m.matter_id() == matter)
+                .count()
+        };
+        let mut saw_smoke = false;
+        for _ in 0..600 {
+            simulator.step(1, false);
+            saw_smoke |= count(&simulator, smoke) > 0;
+        }
+        // The whole plank burned down & the fire went out
+        assert_eq!(count(&simulator, wood), 0);
+        assert_eq!(count(&simulator, fire), 0);
+        assert!(saw_smoke);
+    }
+
+    #[test]
+    fn test_cpu_heat_diffuses_and_boils_water() {
+        let (width, height) = (30, 20);
+        let mut simulator = CpuSimulator::new(width, height, MatterRegistry::default());
+        let (water, steam, fire) = (
+            test_matter("Water"),
+            test_matter("Steam"),
+            test_matter("Fire"),
+        );
+        simulator.draw_matter(Vec2::new(0.0, 0.0), Vec2::new(29.0, 0.0), 0.5, water);
+        simulator.draw_matter(Vec2::new(15.0, 1.0), Vec2::new(15.0, 1.0), 0.5, fire);
+        let count = |simulator: &CpuSimulator, matter: MatterId| {
+            simulator
+                .cells()
+                .iter()
+                .filter(|m| m.matter_id() == matter)
+                .count()
+        };
+        let water_count = count(&simulator, water);
+        let mut boiled = false;
+        for _ in 0..10 {
+            simulator.step(1, false);
+            boiled |= count(&simulator, steam) > 0;
+        }
+        // Heat spread from the fire, water next to it boiled
+        assert!(boiled);
+        assert!(count(&simulator, water) < water_count);
+        let near = simulator.temperature(IVec2::new(15, 3)).unwrap();
+        let far = simulator.temperature(IVec2::new(0, 19)).unwrap();
+        assert!(near > far);
+        assert_eq!(far, AMBIENT_TEMPERATURE);
+
+        // Once the fire is out heat only spreads, the total stays the same
+        for _ in 0..100 {
+            simulator.step(1, false);
+        }
+        assert_eq!(count(&simulator, fire), 0);
+        let total_heat = |simulator: &CpuSimulator| simulator.temperature_in.iter().sum::<f32>();
+        let heat = total_heat(&simulator);
+        simulator.step(5, false);
+        assert!((total_heat(&simulator) - heat).abs() / heat < 1e-4);
+    }
+
+    #[test]
+    fn test_cpu_reactions() {
+        let (width, height) = (20, 12);
+        let mut simulator = CpuSimulator::new(width, height, MatterRegistry::default());
+        let count = |simulator: &CpuSimulator, matter: &str| {
+            simulator
+                .cells()
+                .iter()
+                .filter(|m| m.matter_id() == test_matter(matter))
+                .count()
+        };
+        // Lava poured into a water pool cools into stone
+        simulator.draw_matter(
+            Vec2::new(0.0, 1.0),
+            Vec2::new(19.0, 1.0),
+            1.0,
+            test_matter("Water"),
+        );
+        simulator.draw_matter(
+            Vec2::new(10.0, 8.0),
+            Vec2::new(10.0, 8.0),
+            1.0,
+            test_matter("Lava"),
+        );
+        for _ in 0..50 {
+            simulator.step(1, false);
+        }
+        assert!(count(&simulator, "Stone") > 0);
+
+        // Acid dissolves sand, both disappear
+        let mut simulator = CpuSimulator::new(width, height, MatterRegistry::default());
+        simulator.draw_matter(
+            Vec2::new(0.0, 1.0),
+            Vec2::new(19.0, 1.0),
+            1.0,
+            test_matter("Sand"),
+        );
+        simulator.draw_matter(
+            Vec2::new(10.0, 6.0),
+            Vec2::new(10.0, 6.0),
+            1.0,
+            test_matter("Acid"),
+        );
+        let (sand, acid) = (count(&simulator, "Sand"), count(&simulator, "Acid"));
+        for _ in 0..100 {
+            simulator.step(1, false);
+        }
+        assert_eq!(count(&simulator, "Acid"), 0);
+        assert_eq!(count(&simulator, "Sand"), sand - acid);
+    }
+
+    #[test]
+    fn test_cpu_seed_reproducible() {
+        let run = |seed: u32| {
+            let mut simulator = CpuSimulator::new(40, 24, MatterRegistry::default());
+            simulator.set_seed(seed);
+            draw_test_scene(&mut |start, end, radius, matter| {
+                simulator.draw_matter(start, end, radius, matter)
+            });
+            let pos = Vec2::new(20.0, 4.0);
+            simulator.draw_matter(pos, pos, 3.0, test_matter("Smoke"));
+            for _ in 0..20 {
+                simulator.step(1, false);
+            }
+            simulator.cells().to_vec()
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+
+    #[test]
+    fn test_cpu_gas_rises_and_fades() {
+        let (width, height) = (30, 30);
+        let registry = MatterRegistry::default();
+        let smoke = test_matter("Smoke");
+        let lifetime = registry.get(smoke).unwrap().lifetime;
+        let mut simulator = CpuSimulator::new(width, height, registry);
+        simulator.draw_matter(Vec2::new(15.0, 4.0), Vec2::new(15.0, 4.0), 2.0, smoke);
+        let smoke_cells = |simulator: &CpuSimulator| {
+            simulator
+                .cells()
+                .iter()
+                .enumerate()
+                .filter(|(_, m)| m.matter_id() == smoke)
+                .map(|(i, m)| {
+                    (
+                        IVec2::new((i as u32 % width) as i32, (i as u32 / width) as i32),
+                        *m,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let count = smoke_cells(&simulator).len();
+        assert!(smoke_cells(&simulator)
+            .iter()
+            .all(|(_, m)| m.lifetime() == lifetime));
+        for _ in 0..40 {
+            simulator.step(1, false);
+        }
+        // Risen to the ceiling & drifted sideways, nothing lost yet
+        let cells = smoke_cells(&simulator);
+        assert_eq!(cells.len(), count);
+        assert!(cells.iter().all(|(pos, _)| pos.y >= height as i32 - 4));
+        assert!(cells.iter().any(|(pos, _)| (pos.x - 15).abs() > 2));
+        assert!(cells.iter().all(|(_, m)| m.lifetime() == lifetime - 40));
+        // Faded away at the end of its lifetime
+        for _ in 40..lifetime {
+            simulator.step(1, false);
+        }
+        assert!(smoke_cells(&simulator).is_empty());
+    }
+
+    #[test]
+    fn test_cpu_matches_gpu() {
+        // 6 x 4 tiles, so matter crosses tile borders & settled tiles fall asleep next to awake ones
+        let (width, height) = (96, 64);
+        let context = VulkanoContext::default();
+        let mut gpu = CASimulator::new(
+            context.compute_queue(),
+            CASimulatorConfig {
+                canvas_size_x: width,
+                canvas_size_y: height,
+                local_size_x: 16,
+                local_size_y: 16,
+            },
+            MatterRegistry::default(),
+            None,
+        );
+        let mut cpu = CpuSimulator::new(width, height, MatterRegistry::default());
+        gpu.set_seed(42);
+        cpu.set_seed(42);
+        let mut draw = |start: Vec2, end: Vec2, radius: f32, matter: MatterId| {
+            gpu.draw_matter(start, end, radius, matter);
+            cpu.draw_matter(start, end, radius, matter);
+        };
+        // The ledge crosses the tile border at x = 48 & its sand falls across the one at y = 32
+        let offset = Vec2::new(28.0, 20.0);
+        draw_test_scene(&mut |start, end, radius, matter| {
+            draw(start + offset, end + offset, radius, matter)
+        });
+        // Water splashing on the ledge
+        draw(
+            Vec2::new(38.0, 34.0),
+            Vec2::new(40.0, 36.0),
+            2.0,
+            test_matter("Water"),
+        );
+        // Steam rising into the ledge
+        draw(
+            Vec2::new(48.0, 21.0),
+            Vec2::new(54.0, 22.0),
+            1.5,
+            test_matter("Steam"),
+        );
+        // Fire at the ledge's end
+        let pos = Vec2::new(58.0, 27.0);
+        draw(pos, pos, 0.5, test_matter("Fire"));
+        // Snow & gravel piling up with friction on tile corners
+        draw(
+            Vec2::new(14.0, 60.0),
+            Vec2::new(18.0, 56.0),
+            2.5,
+            test_matter("Snow"),
+        );
+        draw(
+            Vec2::new(80.0, 62.0),
+            Vec2::new(80.0, 50.0),
+            2.0,
+            test_matter("Gravel"),
+        );
+        // Water spreading over the floor across tile borders
+        draw(
+            Vec2::new(68.0, 12.0),
+            Vec2::new(68.0, 14.0),
+            2.0,
+            test_matter("Water"),
+        );
+
+        // Matter that only moves, or turns into glass
+        let granular = ["Sand", "Glass", "Gravel", "Snow"].map(test_matter);
+        let mass = |cells: &[MatterWithColor]| {
+            cells
+                .iter()
+                .filter(|m| granular.contains(&m.matter_id()))
+                .count()
+        };
+        let mut expected_mass = mass(cpu.cells());
+        for step in 0..160 {
+            // Pour more onto the settled piles, next to tiles that have fallen asleep
+            if step == 100 {
+                for x in [16.0, 32.0, 80.0] {
+                    let pos = Vec2::new(x, 60.0);
+                    gpu.draw_matter(pos, pos, 1.5, test_matter("Sand"));
+                    cpu.draw_matter(pos, pos, 1.5, test_matter("Sand"));
+                }
+                expected_mass = mass(cpu.cells());
+            }
+            gpu.step(1 + step % 2, step % 7 == 6);
+            cpu.step(1 + step % 2, step % 7 == 6);
+            let grid = gpu.read_grid();
+            assert_eq!(UVec2::new(grid.width(), grid.height()), cpu.canvas_size());
+            for (index, (gpu_matter, cpu_matter)) in
+                grid.cells().iter().zip(cpu.cells()).enumerate()
+            {
+                assert_eq!(
+                    (gpu_matter.matter_id(), gpu_matter.state),
+                    (cpu_matter.matter_id(), cpu_matter.state),
+                    "step {} cell {}",
+                    step,
+                    index
+                );
+            }
+            assert_eq!(mass(grid.cells()), expected_mass, "step {}", step);
+            // The heat pass skips asleep tiles too, but heat must spread the same
+            let temperature = gpu.read_temperature();
+            for (index, (gpu_temperature, cpu_temperature)) in
+                temperature.iter().zip(&cpu.temperature_in).enumerate()
+            {
+                assert!(
+                    (gpu_temperature - cpu_temperature).abs() < 0.01,
+                    "step {} cell {}",
+                    step,
+                    index
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_cpu_color_variation_clamped() {
+        // Snow at its lightest stays white & Fire at its darkest has no blue, instead of wrapping around
+        assert_eq!(variate_color(0xfffafa, 0.03, 1.0), 0xffffff);
+        assert_eq!(variate_color(0xe25822, 0.2, 0.0) & 255, 0);
+    }
+}
@@ -0,0 +1,180 @@
+use bevy::math::{IVec2, UVec2, Vec2};
+
+use crate::matter::MatterWithColor;
+
+/// A rectangle of canvas cells, `origin` being its bottom left cell
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct GridRect {
+    pub origin: UVec2,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl GridRect {
+    pub fn new(origin: UVec2, width: u32, height: u32) -> GridRect {
+        GridRect {
+            origin,
+            width,
+            height,
+        }
+    }
+
+    /// Exclusive top right corner
+    pub fn end(&self) -> UVec2 {
+        self.origin + UVec2::new(self.width, self.height)
+    }
+
+    /// Does the rectangle fit within a canvas of given size?
+    pub fn is_inside(&self, canvas_size: UVec2) -> bool {
+        let fits = |origin: u32, size: u32, canvas_size: u32| {
+            origin
+                .checked_add(size)
+                .map_or(false, |end| end <= canvas_size)
+        };
+        fits(self.origin.x, self.width, canvas_size.x)
+            && fits(self.origin.y, self.height, canvas_size.y)
+    }
+
+    /// Does the rectangle have no cells?
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Smallest rectangle containing both rectangles
+    pub fn union(&self, other: &GridRect) -> GridRect {
+        let origin = self.origin.min(other.origin);
+        let end = self.end().max(other.end());
+        GridRect::new(origin, end.x - origin.x, end.y - origin.y)
+    }
+
+    /// Cells a brush stroke from `start` to `end` can paint, clipped to the canvas. `None` if the stroke
+    /// misses the canvas.
+    pub fn stroke_bounds(
+        start: Vec2,
+        end: Vec2,
+        radius: f32,
+        canvas_size: UVec2,
+    ) -> Option<GridRect> {
+        // One cell of margin for the rounding in `draw_matter_circle` of `draw_matter.glsl`
+        let min = ((start.min(end) - radius).floor() - 1.0).max(Vec2::ZERO);
+        let max = ((start.max(end) + radius).ceil() + 1.0).min(canvas_size.as_vec2() - 1.0);
+        if min.x > max.x || min.y > max.y {
+            return None;
+        }
+        let (min, max) = (min.as_uvec2(), max.as_uvec2());
+        Some(GridRect::new(min, max.x - min.x + 1, max.y - min.y + 1))
+    }
+}
+
+/// A 2D grid of matter, stored row by row starting from the bottom row like the simulation buffers
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MatterGrid {
+    width: u32,
+    height: u32,
+    cells: Vec<MatterWithColor>,
+}
+
+impl MatterGrid {
+    pub fn new(width: u32, height: u32, cells: Vec<MatterWithColor>) -> MatterGrid {
+        assert_eq!(cells.len(), (width * height) as usize);
+        MatterGrid {
+            width,
+            height,
+            cells,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn cells(&self) -> &[MatterWithColor] {
+        &self.cells
+    }
+
+    /// Matter at pos, relative to the grid's bottom left corner
+    pub fn get(&self, pos: IVec2) -> Option<MatterWithColor> {
+        if pos.x >= 0 && pos.x < self.width as i32 && pos.y >= 0 && pos.y < self.height as i32 {
+            Some(self.cells[(pos.y * self.width as i32 + pos.x) as usize])
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{UVec2, Vec2};
+
+    use crate::grid::GridRect;
+
+    #[test]
+    fn test_is_inside() {
+        let canvas_size = UVec2::new(100, 50);
+        assert!(GridRect::new(UVec2::new(90, 0), 10, 50).is_inside(canvas_size));
+        assert!(!GridRect::new(UVec2::new(90, 0), 11, 50).is_inside(canvas_size));
+        // Would overflow u32
+        assert!(!GridRect::new(UVec2::new(u32::MAX, 0), 2, 1).is_inside(canvas_size));
+        assert!(!GridRect::new(UVec2::new(0, 1), 1, u32::MAX).is_inside(canvas_size));
+        // Empty rectangles fit, but have no cells
+        let empty = GridRect::new(UVec2::new(10, 10), 0, 5);
+        assert!(empty.is_inside(canvas_size));
+        assert!(empty.is_empty());
+        assert!(!GridRect::new(UVec2::ZERO, 1, 1).is_empty());
+    }
+
+    #[test]
+    fn test_union() {
+        let a = GridRect::new(UVec2::new(2, 3), 4, 5);
+        let b = GridRect::new(UVec2::new(10, 1), 1, 1);
+        assert_eq!(a.union(&b), GridRect::new(UVec2::new(2, 1), 9, 7));
+        assert_eq!(a.union(&a), a);
+    }
+
+    #[test]
+    fn test_stroke_bounds() {
+        let canvas_size = UVec2::new(100, 50);
+        assert_eq!(
+            GridRect::stroke_bounds(
+                Vec2::new(10.0, 20.0),
+                Vec2::new(30.0, 25.0),
+                2.0,
+                canvas_size
+            ),
+            Some(GridRect::new(UVec2::new(7, 17), 27, 12))
+        );
+        // Clipped to the canvas
+        assert_eq!(
+            GridRect::stroke_bounds(
+                Vec2::new(-5.0, 45.0),
+                Vec2::new(2.0, 60.0),
+                3.0,
+                canvas_size
+            ),
+            Some(GridRect::new(UVec2::new(0, 41), 7, 9))
+        );
+        // Missing the canvas
+        assert_eq!(
+            GridRect::stroke_bounds(
+                Vec2::new(-20.0, 10.0),
+                Vec2::new(-10.0, 10.0),
+                3.0,
+                canvas_size
+            ),
+            None
+        );
+        assert_eq!(
+            GridRect::stroke_bounds(
+                Vec2::new(120.0, 10.0),
+                Vec2::new(120.0, 10.0),
+                3.0,
+                canvas_size
+            ),
+            None
+        );
+    }
+}
@@ -6,7 +6,6 @@ use bevy_vulkano::{
     egui_winit_vulkano::{egui, egui::Ui},
     BevyVulkanoWindows,
 };
-use strum::IntoEnumIterator;
 
 use crate::{
     ca_simulator::CASimulator,
@@ -14,7 +13,7 @@ use crate::{
     cursor_to_world,
     matter::MatterId,
     timer::{RenderTimer, SimTimer},
-    DynamicSettings, MousePos, CANVAS_SIZE_X, CANVAS_SIZE_Y,
+    DynamicSettings, MousePos,
 };
 
 /// Give our text a custom size
@@ -23,6 +22,7 @@ fn sized_text(ui: &mut Ui, text: impl Into<String>, size: f32) {
 }
 
 /// System to generate user interface with egui
+#[allow(clippy::too_many_arguments)]
 pub fn user_interface(
     vulkano_windows: NonSend<BevyVulkanoWindows>,
     diagnostics: Res<Diagnostics>,
@@ -32,6 +32,7 @@ pub fn user_interface(
     sim_timer: Res<SimTimer>,
     render_timer: Res<RenderTimer>,
     mut simulator: ResMut<CASimulator>,
+    mut hovered_matter: Local<Option<MatterId>>,
 ) {
     let (_, gui) = vulkano_windows.get_primary_window_renderer().unwrap();
     let ctx = gui.context();
@@ -45,9 +46,10 @@ pub fn user_interface(
                     sized_text(ui, format!("FPS: {:.2}", avg), size);
                 }
             }
+            let canvas_size = simulator.canvas_size();
             sized_text(
                 ui,
-                format!("Grid size: ({},{})", CANVAS_SIZE_X, CANVAS_SIZE_Y),
+                format!("Grid size: ({},{})", canvas_size.x, canvas_size.y),
                 size,
             );
             sized_text(
@@ -72,14 +74,15 @@ pub fn user_interface(
             ui.add(egui::Slider::new(&mut settings.brush_radius, 0.5..=200.0).text("Brush Size"));
             ui.add(egui::Slider::new(&mut settings.move_steps, 1..=5).text("Move Steps"));
             // Selectable matter
+            let matter_registry = simulator.matter_registry();
             egui::ComboBox::from_label("Matter")
-                .selected_text(format!("{:?}", settings.draw_matter))
+                .selected_text(matter_registry.name(settings.draw_matter))
                 .show_ui(ui, |ui| {
-                    for matter in MatterId::iter() {
+                    for matter in matter_registry.iter() {
                         ui.selectable_value(
                             &mut settings.draw_matter,
-                            matter,
-                            format!("{:?}", matter),
+                            matter.matter_id(),
+                            &matter.name,
                         );
                     }
                 });
@@ -87,12 +90,21 @@ pub fn user_interface(
     let primary = windows.get_primary().unwrap();
     if primary.cursor_position().is_some() {
         let world_pos = cursor_to_world(primary, camera.pos, camera.scale);
-        let sim_pos = MousePos::new(world_pos).canvas_pos();
+        let sim_pos = MousePos::new(world_pos).canvas_pos(simulator.canvas_size());
+        // Query asynchronously, the result shows up a frame or two later
+        simulator.queue_query(sim_pos.as_ivec2());
+        simulator.submit_queries();
+        if let Some(result) = simulator.poll_queries().last() {
+            *hovered_matter = result.matter.map(|m| m.matter_id());
+        }
         egui::containers::show_tooltip_at_pointer(&ctx, egui::Id::new("Hover tooltip"), |ui| {
             ui.label(format!("World: [{:.2}, {:.2}]", world_pos.x, world_pos.y));
             ui.label(format!("Sim: [{:.2}, {:.2}]", sim_pos.x, sim_pos.y));
-            if let Some(matter) = simulator.query_matter(sim_pos.as_ivec2()) {
-                ui.label(format!("Matter: {:?}", matter));
+            if let Some(matter) = *hovered_matter {
+                ui.label(format!(
+                    "Matter: {}",
+                    simulator.matter_registry().name(matter)
+                ));
             }
         });
     }
@@ -0,0 +1,319 @@
+use std::io;
+
+use bevy::math::IVec2;
+use image::{ImageError, Rgba, RgbaImage};
+
+use crate::{
+    grid::MatterGrid,
+    matter::{MatterId, MatterRegistry, MatterWithColor},
+    utils::u32_rgba_to_u8_rgba,
+};
+
+/// How pixel colors are matched against palette colors
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PaletteMatch {
+    /// Pixel color must equal a palette color
+    Exact,
+    /// Pixel becomes the matter of the closest palette color
+    Nearest,
+}
+
+/// Maps image pixel colors to matter. Fully transparent pixels are always `Empty`.
+#[derive(Debug, Clone)]
+pub struct MatterPalette {
+    pub colors: Vec<([u8; 3], MatterId)>,
+    pub matching: PaletteMatch,
+    /// Keep the pixel's color instead of giving cells their matter's color
+    pub keep_pixel_color: bool,
+    /// Cells of matter without a kept pixel color get the registry's matter color
+    pub matter_registry: MatterRegistry,
+}
+
+impl MatterPalette {
+    /// Matter colors of the registry as defined, matched to the nearest color. Defined colors stay
+    /// distinct when the display is grey scaled.
+    pub fn from_registry(matter_registry: &MatterRegistry) -> MatterPalette {
+        MatterPalette {
+            colors: matter_registry
+                .iter()
+                .map(|matter| {
+                    let color = u32_rgba_to_u8_rgba((matter.color << 8) | 255);
+                    ([color[0], color[1], color[2]], matter.matter_id())
+                })
+                .collect(),
+            matching: PaletteMatch::Nearest,
+            keep_pixel_color: false,
+            matter_registry: matter_registry.clone(),
+        }
+    }
+
+    /// Matter for a pixel, `None` if an exact palette doesn't contain its color
+    pub fn matter_for_pixel(&self, rgba: [u8; 4]) -> Option<MatterId> {
+        if rgba[3] == 0 {
+            return Some(MatterId::EMPTY);
+        }
+        let rgb = [rgba[0], rgba[1], rgba[2]];
+        match self.matching {
+            PaletteMatch::Exact => self
+                .colors
+                .iter()
+                .find(|(color, _)| *color == rgb)
+                .map(|(_, matter)| *matter),
+            PaletteMatch::Nearest => self
+                .colors
+                .iter()
+                .min_by_key(|(color, _)| {
+                    color
+                        .iter()
+                        .zip(rgb.iter())
+                        .map(|(&a, &b)| (a as i32 - b as i32).pow(2))
+                        .sum::<i32>()
+                })
+                .map(|(_, matter)| *matter),
+        }
+    }
+
+    /// Convert an image to a matter grid. Image's top row becomes the grid's top row.
+    pub fn matter_grid(&self, image: &RgbaImage) -> io::Result<MatterGrid> {
+        let (width, height) = image.dimensions();
+        let mut cells = Vec::with_capacity((width * height) as usize);
+        // Our grid starts from the bottom row, images from the top row
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let rgba = image.get_pixel(x, y).0;
+                let matter = self.matter_for_pixel(rgba).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Pixel ({},{}) color #{:02x}{:02x}{:02x} is not in palette",
+                            x, y, rgba[0], rgba[1], rgba[2]
+                        ),
+                    )
+                })?;
+                cells.push(if self.keep_pixel_color && rgba[3] != 0 {
+                    self.matter_registry
+                        .with_initial_state(MatterWithColor::with_color(matter, [
+                            rgba[0], rgba[1], rgba[2],
+                        ]))
+                } else {
+                    self.matter_registry.matter_with_color(matter)
+                });
+            }
+        }
+        Ok(MatterGrid::new(width, height, cells))
+    }
+}
+
+/// 0-255 sRGB from 0-255 linear, the inverse of `linear_from_srgb` in `color.glsl`. Dark colors lose
+/// precision, because the canvas image stores linear colors in 8 bits.
+pub fn srgb_from_linear(linear: u8) -> u8 {
+    let linear = linear as f32 / 255.0;
+    let srgb = if linear < 10.31475 / 3294.6 {
+        linear * 3294.6
+    } else {
+        269.025 * linear.powf(1.0 / 2.4) - 14.025
+    };
+    srgb.round().clamp(0.0, 255.0) as u8
+}
+
+/// Image from the canvas image's linear rgba pixels, rows starting from the bottom row
+pub fn color_image_from_linear_rgba(width: u32, height: u32, pixels: &[u8]) -> RgbaImage {
+    assert_eq!(pixels.len(), (width * height * 4) as usize);
+    RgbaImage::from_fn(width, height, |x, y| {
+        // Image's top row is our canvas' top row
+        let i = (((height - 1 - y) * width + x) * 4) as usize;
+        Rgba([
+            srgb_from_linear(pixels[i]),
+            srgb_from_linear(pixels[i + 1]),
+            srgb_from_linear(pixels[i + 2]),
+            pixels[i + 3],
+        ])
+    })
+}
+
+/// Distinct color for each matter id, empty being black
+pub fn matter_false_color(matter: u8) -> [u8; 3] {
+    if matter == 0 {
+        return [0, 0, 0];
+    }
+    // Golden angle steps spread the hues of consecutive ids apart
+    let hue = (matter as f32 * 137.507_77) % 360.0 / 60.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    [(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8]
+}
+
+/// False colored image of the matter ids of a grid
+pub fn matter_id_image(grid: &MatterGrid) -> RgbaImage {
+    RgbaImage::from_fn(grid.width(), grid.height(), |x, y| {
+        let pos = IVec2::new(x as i32, (grid.height() - 1 - y) as i32);
+        let [r, g, b] = matter_false_color(grid.get(pos).unwrap().value as u8);
+        Rgba([r, g, b, 255])
+    })
+}
+
+/// Image errors as io errors
+pub fn image_error_to_io(error: ImageError) -> io::Error {
+    match error {
+        ImageError::IoError(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::IVec2;
+    use image::{Rgba, RgbaImage};
+
+    use crate::{
+        grid::MatterGrid,
+        image_io::{
+            color_image_from_linear_rgba, matter_false_color, matter_id_image, srgb_from_linear,
+            MatterPalette, PaletteMatch,
+        },
+        matter::{test_matter, MatterId, MatterRegistry, MatterWithColor},
+    };
+
+    fn test_palette(matching: PaletteMatch) -> MatterPalette {
+        MatterPalette {
+            colors: vec![
+                ([255, 255, 255], MatterId::EMPTY),
+                ([255, 255, 0], test_matter("Sand")),
+                ([128, 64, 0], test_matter("Wood")),
+            ],
+            matching,
+            keep_pixel_color: false,
+            matter_registry: MatterRegistry::default(),
+        }
+    }
+
+    #[test]
+    fn test_palette_matching() {
+        let exact = test_palette(PaletteMatch::Exact);
+        let nearest = test_palette(PaletteMatch::Nearest);
+        assert_eq!(
+            exact.matter_for_pixel([255, 255, 0, 255]),
+            Some(test_matter("Sand"))
+        );
+        assert_eq!(exact.matter_for_pixel([250, 250, 0, 255]), None);
+        assert_eq!(
+            nearest.matter_for_pixel([250, 250, 0, 255]),
+            Some(test_matter("Sand"))
+        );
+        assert_eq!(
+            nearest.matter_for_pixel([100, 50, 10, 255]),
+            Some(test_matter("Wood"))
+        );
+        // Transparent is empty regardless of color
+        assert_eq!(
+            exact.matter_for_pixel([128, 64, 0, 0]),
+            Some(MatterId::EMPTY)
+        );
+    }
+
+    #[test]
+    fn test_palette_from_registry() {
+        let registry = MatterRegistry::default();
+        let palette = MatterPalette {
+            matching: PaletteMatch::Exact,
+            ..MatterPalette::from_registry(&registry)
+        };
+        for matter in registry.iter() {
+            let color = matter.color;
+            let rgba = [(color >> 16) as u8, (color >> 8) as u8, color as u8, 255];
+            assert_eq!(palette.matter_for_pixel(rgba), Some(matter.matter_id()));
+        }
+    }
+
+    #[test]
+    fn test_image_to_matter_grid() {
+        // Wood on the top row, sand on the bottom row
+        let mut image = RgbaImage::from_pixel(3, 2, Rgba([0, 0, 0, 0]));
+        image.put_pixel(1, 0, Rgba([128, 64, 0, 255]));
+        image.put_pixel(2, 1, Rgba([250, 250, 10, 255]));
+
+        let registry = MatterRegistry::default();
+        let grid = test_palette(PaletteMatch::Nearest)
+            .matter_grid(&image)
+            .unwrap();
+        assert_eq!((grid.width(), grid.height()), (3, 2));
+        assert_eq!(
+            grid.get(IVec2::new(1, 1)),
+            Some(registry.matter_with_color(test_matter("Wood")))
+        );
+        assert_eq!(
+            grid.get(IVec2::new(2, 0)),
+            Some(registry.matter_with_color(test_matter("Sand")))
+        );
+        assert_eq!(
+            grid.get(IVec2::new(0, 0)),
+            Some(registry.matter_with_color(MatterId::EMPTY))
+        );
+
+        let grid = MatterPalette {
+            keep_pixel_color: true,
+            ..test_palette(PaletteMatch::Nearest)
+        }
+        .matter_grid(&image)
+        .unwrap();
+        assert_eq!(
+            grid.get(IVec2::new(2, 0)),
+            Some(MatterWithColor::with_color(test_matter("Sand"), [
+                250, 250, 10
+            ]))
+        );
+
+        assert!(test_palette(PaletteMatch::Exact)
+            .matter_grid(&image)
+            .is_err());
+    }
+
+    #[test]
+    fn test_srgb_from_linear() {
+        // Same as linear_from_srgb in color.glsl
+        let linear_from_srgb = |srgb: f32| {
+            if srgb < 10.31475 {
+                srgb / 3294.6
+            } else {
+                ((srgb + 14.025) / 269.025).powf(2.4)
+            }
+        };
+        assert_eq!(srgb_from_linear(0), 0);
+        assert_eq!(srgb_from_linear(255), 255);
+        // Brighter colors survive the round trip through 8 bit linear
+        for srgb in [100u8, 128, 194, 250] {
+            let linear = (linear_from_srgb(srgb as f32) * 255.0).round() as u8;
+            assert!((srgb_from_linear(linear) as i32 - srgb as i32).abs() <= 4);
+        }
+    }
+
+    #[test]
+    fn test_export_images_flip_rows() {
+        // Bottom row white, top row black
+        let pixels = [[255u8; 8], [0, 0, 0, 255, 0, 0, 0, 255]].concat();
+        let image = color_image_from_linear_rgba(2, 2, &pixels);
+        assert_eq!(image.get_pixel(1, 1), &Rgba([255, 255, 255, 255]));
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+
+        let registry = MatterRegistry::default();
+        let grid = MatterGrid::new(1, 2, vec![
+            registry.matter_with_color(test_matter("Sand")),
+            registry.matter_with_color(MatterId::EMPTY),
+        ]);
+        let image = matter_id_image(&grid);
+        let sand = matter_false_color(test_matter("Sand").0);
+        assert_eq!(
+            image.get_pixel(0, 1),
+            &Rgba([sand[0], sand[1], sand[2], 255])
+        );
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+        assert_ne!(sand, matter_false_color(test_matter("Wood").0));
+    }
+}
@@ -1,14 +1,25 @@
+// The loaders generated by `vulkano_shaders::shader!` return `Ok(..?)`
+#![allow(clippy::needless_question_mark)]
+
 mod ca_simulator;
 mod camera;
+#[cfg(test)]
+mod cpu_simulator;
+mod grid;
 mod gui;
+mod image_io;
 mod matter;
+mod pipeline_cache;
 mod quad_pipeline;
+mod query;
 mod render;
+mod snapshot;
 mod timer;
 mod utils;
 mod vertex;
 
 use bevy::{
+    app::AppExit,
     input::mouse::MouseWheel,
     prelude::*,
     time::FixedTimestep,
@@ -19,10 +30,13 @@ use bevy_vulkano::{
 };
 
 use crate::{
-    ca_simulator::CASimulator,
+    ca_simulator::{CASimulator, CASimulatorConfig},
     camera::OrthographicCamera,
+    grid::GridRect,
     gui::user_interface,
-    matter::MatterId,
+    image_io::{MatterPalette, PaletteMatch},
+    matter::{MatterId, MatterRegistry},
+    pipeline_cache::PipelineCacheFile,
     render::FillScreenRenderPass,
     timer::{PerformanceTimer, RenderTimer, SimTimer},
     utils::{cursor_to_world, MousePos},
@@ -34,14 +48,25 @@ pub const CANVAS_SIZE_X: u32 = 4096;
 pub const CANVAS_SIZE_Y: u32 = 4096;
 pub const LOCAL_SIZE_X: u32 = 32;
 pub const LOCAL_SIZE_Y: u32 = 32;
-pub const NUM_WORK_GROUPS_X: u32 = CANVAS_SIZE_X / LOCAL_SIZE_X;
-pub const NUM_WORK_GROUPS_Y: u32 = CANVAS_SIZE_Y / LOCAL_SIZE_Y;
 pub const SIM_FPS: f64 = 60.0;
 /// Grey scale theme for cool looks
 pub const GREY_SCALE: bool = true;
 pub const CLEAR_COLOR: [f32; 4] = if GREY_SCALE { [0.8; 4] } else { [0.0; 4] };
 pub const EMPTY_COLOR: u32 = if GREY_SCALE { 0xffffffff } else { 0x0 };
 pub const CAMERA_MOVE_SPEED: f32 = 200.0;
+/// Temperature of the canvas at start & after loading
+pub const AMBIENT_TEMPERATURE: f32 = 20.0;
+/// Seed of the simulation's random numbers, runs with the same seed & input play out the same
+pub const WORLD_SEED: u32 = 0x5eed;
+/// Matter definitions loaded at startup, the built-in definitions are used if loading fails
+pub const MATTER_DEFINITIONS_PATH: &str = "matter_definitions.ron";
+/// Where F5 saves & F9 loads the simulation state
+pub const SNAPSHOT_PATH: &str = "snapshot.casn";
+/// Where F12 saves the canvas colors & F11 the matter ids
+pub const SCREENSHOT_PATH: &str = "screenshot.png";
+pub const MATTER_SCREENSHOT_PATH: &str = "screenshot_matter.png";
+/// Where compiled pipelines are cached between runs, one file per device & driver
+pub const PIPELINE_CACHE_DIR: &str = "pipeline_cache";
 
 pub struct DynamicSettings {
     pub brush_radius: f32,
@@ -55,7 +80,8 @@ impl Default for DynamicSettings {
         Self {
             brush_radius: 4.0,
             move_steps: 1,
-            draw_matter: MatterId::Sand,
+            // Set from the loaded matter registry at startup
+            draw_matter: MatterId::EMPTY,
             is_paused: false,
         }
     }
@@ -91,6 +117,9 @@ fn main() {
         .add_startup_system(setup)
         .add_system(close_on_esc)
         .add_system(input_actions)
+        .add_system(snapshot_actions)
+        .add_system(screenshot_actions)
+        .add_system(clear_actions)
         .add_system(update_camera)
         .add_system(update_mouse)
         .add_system(draw_matter)
@@ -105,38 +134,77 @@ fn main() {
         .add_system(user_interface.after(simulate))
         // Render after update
         .add_system_to_stage(CoreStage::PostUpdate, render)
+        // Exit is requested at the latest in PostUpdate
+        .add_system_to_stage(CoreStage::Last, save_pipeline_cache)
         .run();
 }
 
 /// Creates our simulation & render pipelines
 fn setup(mut commands: Commands, vulkano_windows: NonSend<BevyVulkanoWindows>) {
     let (primary_window_renderer, gui) = vulkano_windows.get_primary_window_renderer().unwrap();
+    // Pipelines compiled on earlier runs are loaded from the cache
+    let pipeline_cache = PipelineCacheFile::load(
+        primary_window_renderer.graphics_queue().device().clone(),
+        PIPELINE_CACHE_DIR,
+    );
     // Create our render pass
     let fill_screen = FillScreenRenderPass::new(
         primary_window_renderer.graphics_queue(),
         primary_window_renderer.swapchain_format(),
+        Some(pipeline_cache.cache.clone()),
     );
 
+    let matter_registry = MatterRegistry::load(MATTER_DEFINITIONS_PATH).unwrap_or_else(|e| {
+        bevy::log::error!(
+            "Failed to load {}, using built-in matter: {}",
+            MATTER_DEFINITIONS_PATH,
+            e
+        );
+        MatterRegistry::default()
+    });
+    let settings = DynamicSettings {
+        draw_matter: matter_registry.default_brush(),
+        ..DynamicSettings::default()
+    };
     // Use same queue for compute
-    let mut sim_pipeline = CASimulator::new(primary_window_renderer.compute_queue());
+    let mut sim_pipeline = CASimulator::new(
+        primary_window_renderer.compute_queue(),
+        CASimulatorConfig::default(),
+        matter_registry,
+        Some(pipeline_cache.cache.clone()),
+    );
+    sim_pipeline.set_seed(WORLD_SEED);
     // Ensure bg is white for empty when grey scale...
     if GREY_SCALE {
-        let start = Vec2::new(CANVAS_SIZE_X as f32, CANVAS_SIZE_Y as f32) / 2.0;
+        let canvas_size = sim_pipeline.canvas_size().as_vec2();
+        let start = canvas_size / 2.0;
         let end = start;
-        sim_pipeline.draw_matter(start, end, CANVAS_SIZE_X as f32, MatterId::Empty);
+        sim_pipeline.draw_matter(start, end, canvas_size.max_element(), MatterId::EMPTY);
+    }
+    // Optionally start from an image given as an argument, its colors matched exactly with `--exact`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(path) = args.iter().find(|arg| !arg.starts_with("--")) {
+        let mut palette = MatterPalette::from_registry(sim_pipeline.matter_registry());
+        if args.iter().any(|arg| arg == "--exact") {
+            palette.matching = PaletteMatch::Exact;
+        }
+        if let Err(e) = sim_pipeline.import_png(path, UVec2::ZERO, &palette) {
+            bevy::log::error!("Failed to import {}: {}", path, e);
+        }
     }
     // Create simple orthographic camera
     let mut camera = OrthographicCamera::default();
     // Zoom camera to fit vertical pixels
-    camera.zoom_to_fit_vertical_pixels(CANVAS_SIZE_Y, HEIGHT as u32);
+    camera.zoom_to_fit_vertical_pixels(sim_pipeline.canvas_size().y, HEIGHT as u32);
     // Simulation performance timer
     let perf_timer = PerformanceTimer::new();
     let render_timer = PerformanceTimer::new();
     // Insert resources
     commands.insert_resource(fill_screen);
+    commands.insert_resource(pipeline_cache);
     commands.insert_resource(sim_pipeline);
     commands.insert_resource(camera);
-    commands.insert_resource(DynamicSettings::default());
+    commands.insert_resource(settings);
     commands.insert_resource(PreviousMousePos(None));
     commands.insert_resource(CurrentMousePos(None));
     commands.insert_resource(SimTimer(perf_timer));
@@ -161,13 +229,14 @@ fn draw_matter(
 ) {
     if let Some(current) = current.0 {
         if mouse_button_input.pressed(MouseButton::Left) {
-            let end = current.canvas_pos();
+            let canvas_size = simulator.canvas_size();
+            let end = current.canvas_pos(canvas_size);
             let start = if let Some(prev) = prev.0 {
-                prev.canvas_pos()
+                prev.canvas_pos(canvas_size)
             } else {
                 end
             };
-            simulator.draw_matter(start, end, settings.brush_radius, settings.draw_matter);
+            simulator.queue_stroke(start, end, settings.brush_radius, settings.draw_matter);
         }
     }
 }
@@ -285,3 +354,56 @@ fn input_actions(
         settings.is_paused = !settings.is_paused;
     }
 }
+
+/// Save (F5) and load (F9) simulation state
+fn snapshot_actions(mut simulator: ResMut<CASimulator>, keyboard_input: Res<Input<KeyCode>>) {
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        match simulator.save_snapshot(SNAPSHOT_PATH) {
+            Ok(()) => bevy::log::info!("Saved snapshot to {}", SNAPSHOT_PATH),
+            Err(e) => bevy::log::error!("Failed to save snapshot: {}", e),
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::F9) {
+        match simulator.load_snapshot(SNAPSHOT_PATH) {
+            Ok(()) => bevy::log::info!("Loaded snapshot from {}", SNAPSHOT_PATH),
+            Err(e) => bevy::log::error!("Failed to load snapshot: {}", e),
+        }
+    }
+}
+
+/// Save the whole canvas as a png (F12) or as a matter id map (F11)
+fn screenshot_actions(simulator: Res<CASimulator>, keyboard_input: Res<Input<KeyCode>>) {
+    if keyboard_input.just_pressed(KeyCode::F12) {
+        match simulator.export_color_png(SCREENSHOT_PATH) {
+            Ok(()) => bevy::log::info!("Saved screenshot to {}", SCREENSHOT_PATH),
+            Err(e) => bevy::log::error!("Failed to save screenshot: {}", e),
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::F11) {
+        match simulator.export_matter_png(MATTER_SCREENSHOT_PATH) {
+            Ok(()) => bevy::log::info!("Saved matter map to {}", MATTER_SCREENSHOT_PATH),
+            Err(e) => bevy::log::error!("Failed to save matter map: {}", e),
+        }
+    }
+}
+
+/// Clear the whole canvas (Delete)
+fn clear_actions(mut simulator: ResMut<CASimulator>, keyboard_input: Res<Input<KeyCode>>) {
+    if keyboard_input.just_pressed(KeyCode::Delete) {
+        let canvas_size = simulator.canvas_size();
+        let canvas = GridRect::new(UVec2::ZERO, canvas_size.x, canvas_size.y);
+        simulator.fill_region(canvas, MatterId::EMPTY);
+    }
+}
+
+/// Save the pipeline cache on exit, so the next run doesn't need to compile pipelines again
+fn save_pipeline_cache(
+    mut app_exit_events: EventReader<AppExit>,
+    pipeline_cache: Res<PipelineCacheFile>,
+) {
+    if app_exit_events.iter().next().is_some() {
+        if let Err(e) = pipeline_cache.save() {
+            bevy::log::error!("Failed to save pipeline cache: {}", e);
+        }
+    }
+}
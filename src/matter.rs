@@ -1,37 +1,319 @@
-use strum_macros::EnumIter;
+use std::{fs, io, path::Path};
+
+use bytemuck::{Pod, Zeroable};
+use ron::extensions::Extensions;
+use serde::Deserialize;
 
 use crate::{
     utils::{grey_scale_u32, u32_rgba_to_u8_rgba, u8_rgba_to_u32_rgba},
     EMPTY_COLOR, GREY_SCALE,
 };
 
-/// Matter Id representing matter that we simulate
-#[repr(u8)]
-#[derive(EnumIter, Debug, Copy, Clone, Eq, PartialEq)]
-pub enum MatterId {
-    Empty = 0,
-    Sand = 1,
-    Wood = 2,
+/// Definitions used when no matter definition file is given
+const DEFAULT_MATTER_DEFINITIONS: &str = include_str!("../matter_definitions.ron");
+
+/// Matter Id representing matter that we simulate, see [`MatterRegistry`]
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct MatterId(pub u8);
+
+impl MatterId {
+    pub const EMPTY: MatterId = MatterId(0);
 }
 
-impl Default for MatterId {
-    fn default() -> Self {
-        MatterId::Empty
+/// Behaviour of matter in the simulation kernels, same flags as in `matter.glsl`
+#[derive(Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MatterBehaviour {
+    /// Falls down on empty
+    Gravity,
+    /// Slides down diagonally on empty
+    Slides,
+    /// Is never moved
+    Static,
+    /// Spreads sideways on empty, see `dispersion`
+    Liquid,
+    /// Rises up & drifts sideways on empty, fades out after `lifetime`
+    Gas,
+    /// Sets flammable neighbors on fire, burns into `burns_into` after `lifetime`
+    Burning,
+}
+
+impl MatterBehaviour {
+    pub fn flag(&self) -> u32 {
+        1 << (*self as u32)
     }
 }
 
-impl From<u8> for MatterId {
-    fn from(item: u8) -> Self {
-        unsafe { std::mem::transmute(item) }
+/// Matter turns into `into` when it crosses `temperature`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Transition {
+    pub temperature: f32,
+    /// Name of the matter it turns into
+    pub into: String,
+}
+
+/// Matter touching matter named `with` turns into `into` & the other matter into `other_into`, with
+/// `probability` per move step
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Reaction {
+    pub with: String,
+    pub into: String,
+    pub other_into: String,
+    pub probability: f32,
+}
+
+/// A matter as described in a matter definition file
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct MatterDefinition {
+    pub name: String,
+    pub id: u8,
+    /// Color as 0xrrggbb
+    pub color: u32,
+    /// How much drawn cells' colors are randomly brightened or darkened (0-1)
+    #[serde(default)]
+    pub color_variation: f32,
+    #[serde(default)]
+    pub behaviour: Vec<MatterBehaviour>,
+    /// How many cells liquid can spread (or gas drift) sideways per move step
+    #[serde(default)]
+    pub dispersion: u32,
+    /// How many move steps gas lasts (at most 255), 0 lasts forever
+    #[serde(default)]
+    pub lifetime: u32,
+    /// Matter with gravity sinks through liquid & gas of lower density
+    #[serde(default)]
+    pub density: f32,
+    /// Chance a sliding cell holds its place instead of sliding (0-1), higher piles up steeper
+    #[serde(default)]
+    pub friction: f32,
+    /// Chance to catch fire from each burning neighbor per move step (0-1)
+    #[serde(default)]
+    pub flammability: f32,
+    /// Name of the matter burning matter leaves behind, `Empty` if not given
+    #[serde(default)]
+    pub burns_into: Option<String>,
+    /// How much heat flows to & from neighbors per step (0-1)
+    #[serde(default)]
+    pub conductivity: f32,
+    /// Heat sources hold their cells at this temperature
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    /// Turns into other matter above a temperature, e.g. water boiling
+    #[serde(default)]
+    pub above: Option<Transition>,
+    /// Turns into other matter below a temperature, e.g. steam condensing
+    #[serde(default)]
+    pub below: Option<Transition>,
+    /// Reactions with other matter, each pair of matter can only react one way
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+}
+
+impl MatterDefinition {
+    pub fn matter_id(&self) -> MatterId {
+        MatterId(self.id)
+    }
+
+    /// Behaviour flags as in `matter.glsl`
+    pub fn flags(&self) -> u32 {
+        self.behaviour.iter().fold(0, |flags, b| flags | b.flag())
+    }
+
+    /// Names of other matter this matter can turn into or react with
+    pub fn referenced_matter(&self) -> impl Iterator<Item = &String> {
+        self.burns_into
+            .iter()
+            .chain(self.above.iter().map(|t| &t.into))
+            .chain(self.below.iter().map(|t| &t.into))
+            .chain(
+                self.reactions
+                    .iter()
+                    .flat_map(|r| [&r.with, &r.into, &r.other_into]),
+            )
     }
 }
 
-impl MatterId {
-    fn color_rgba_u8(&self) -> [u8; 4] {
-        let color = match *self {
-            MatterId::Empty => EMPTY_COLOR,
-            MatterId::Sand => 0xc2b280ff,
-            MatterId::Wood => 0xba8c63ff,
+/// Matter definition as uploaded to the kernels, must match `MatterDefinition` in `matter.glsl`
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, Zeroable, Pod)]
+pub struct MatterDefinitionGpu {
+    pub flags: u32,
+    pub color_variation: f32,
+    pub dispersion: u32,
+    pub lifetime: u32,
+    pub density: f32,
+    pub friction: f32,
+    pub flammability: f32,
+    /// Packed [`MatterWithColor::value`] of the matter left behind
+    pub burns_into: u32,
+    pub conductivity: f32,
+    pub is_heat_source: u32,
+    pub temperature: f32,
+    /// Infinity if there's no transition above
+    pub above_temperature: f32,
+    pub above_into: u32,
+    /// Negative infinity if there's no transition below
+    pub below_temperature: f32,
+    pub below_into: u32,
+}
+
+/// One side of a reaction as uploaded to the kernels, must match `Reaction` in `matter.glsl`
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Zeroable, Pod)]
+pub struct ReactionGpu {
+    /// 0 if the matter doesn't react
+    pub probability: f32,
+    /// Packed [`MatterWithColor::value`] of the matter it turns into
+    pub into: u32,
+}
+
+/// All matter we simulate, indexed by matter id
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatterRegistry {
+    definitions: Vec<Option<MatterDefinition>>,
+    /// Reaction of matter a touching matter b at a * 256 + b
+    reactions: Vec<ReactionGpu>,
+}
+
+impl Default for MatterRegistry {
+    fn default() -> Self {
+        MatterRegistry::from_ron(DEFAULT_MATTER_DEFINITIONS).unwrap()
+    }
+}
+
+impl MatterRegistry {
+    /// Load matter definitions from a RON file
+    pub fn load(path: impl AsRef<Path>) -> io::Result<MatterRegistry> {
+        MatterRegistry::from_ron(&fs::read_to_string(path)?)
+    }
+
+    /// Parse matter definitions from a RON list of [`MatterDefinition`]s
+    pub fn from_ron(ron: &str) -> io::Result<MatterRegistry> {
+        // Optional fields can be given without `Some(...)`
+        let options = ron::Options::default().with_default_extension(Extensions::IMPLICIT_SOME);
+        let list: Vec<MatterDefinition> = options
+            .from_str(ron)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        MatterRegistry::new(list)
+    }
+
+    pub fn new(list: Vec<MatterDefinition>) -> io::Result<MatterRegistry> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut definitions = vec![None; 256];
+        for definition in list {
+            if definition.name.is_empty() || definition.name.len() > 255 {
+                return Err(invalid(format!(
+                    "Invalid matter name length for id {}",
+                    definition.id
+                )));
+            }
+            if definitions
+                .iter()
+                .flatten()
+                .any(|d: &MatterDefinition| d.name == definition.name)
+            {
+                return Err(invalid(format!("Duplicate matter {}", definition.name)));
+            }
+            if !definition.density.is_finite() {
+                return Err(invalid(format!("Invalid density of {}", definition.name)));
+            }
+            if !(0.0..=1.0).contains(&definition.friction) {
+                return Err(invalid(format!(
+                    "Friction of {} is not within 0-1",
+                    definition.name
+                )));
+            }
+            if !(0.0..=1.0).contains(&definition.flammability) {
+                return Err(invalid(format!(
+                    "Flammability of {} is not within 0-1",
+                    definition.name
+                )));
+            }
+            if !(0.0..=1.0).contains(&definition.conductivity) {
+                return Err(invalid(format!(
+                    "Conductivity of {} is not within 0-1",
+                    definition.name
+                )));
+            }
+            if definition.lifetime > LIFETIME_MASK {
+                return Err(invalid(format!(
+                    "Lifetime of {} is over {}",
+                    definition.name, LIFETIME_MASK
+                )));
+            }
+            let id = definition.id as usize;
+            if definitions[id].is_some() {
+                return Err(invalid(format!("Duplicate matter id {}", id)));
+            }
+            definitions[id] = Some(definition);
+        }
+        let mut registry = match &definitions[0] {
+            Some(empty) if empty.name == "Empty" => MatterRegistry {
+                definitions,
+                // Filled once all matter is known
+                reactions: vec![],
+            },
+            _ => return Err(invalid("Matter id 0 must be Empty".to_string())),
+        };
+        for definition in registry.iter() {
+            for name in definition.referenced_matter() {
+                if registry.find(name).is_none() {
+                    return Err(invalid(format!(
+                        "{} refers to unknown matter {}",
+                        definition.name, name
+                    )));
+                }
+            }
+        }
+        registry.reactions = registry.reaction_table()?;
+        Ok(registry)
+    }
+
+    pub fn get(&self, id: MatterId) -> Option<&MatterDefinition> {
+        self.definitions[id.0 as usize].as_ref()
+    }
+
+    /// All matter in id order
+    pub fn iter(&self) -> impl Iterator<Item = &MatterDefinition> {
+        self.definitions.iter().flatten()
+    }
+
+    /// Id of matter by name
+    pub fn find(&self, name: &str) -> Option<MatterId> {
+        self.iter().find(|d| d.name == name).map(|d| d.matter_id())
+    }
+
+    /// Matter drawn before any is picked, Sand or the first non-empty matter if there is no Sand
+    pub fn default_brush(&self) -> MatterId {
+        self.find("Sand")
+            .or_else(|| {
+                self.iter()
+                    .map(|d| d.matter_id())
+                    .find(|&id| id != MatterId::EMPTY)
+            })
+            .unwrap_or(MatterId::EMPTY)
+    }
+
+    /// Name of matter, unknown ids are shown by their number
+    pub fn name(&self, id: MatterId) -> String {
+        self.get(id)
+            .map(|d| d.name.clone())
+            .unwrap_or_else(|| format!("Unknown({})", id.0))
+    }
+
+    /// Does matter have the behaviour? Unknown matter has no behaviour.
+    #[cfg(test)]
+    pub fn has(&self, id: MatterId, behaviour: MatterBehaviour) -> bool {
+        self.get(id)
+            .map(|d| d.behaviour.contains(&behaviour))
+            .unwrap_or(false)
+    }
+
+    /// Color of the matter as displayed
+    pub fn color_rgba_u8(&self, id: MatterId) -> [u8; 4] {
+        let color = if id == MatterId::EMPTY {
+            EMPTY_COLOR
+        } else {
+            self.get(id).map(|d| (d.color << 8) | 255).unwrap_or(0xff)
         };
         if GREY_SCALE {
             u32_rgba_to_u8_rgba(grey_scale_u32(color))
@@ -39,25 +321,176 @@ impl MatterId {
             u32_rgba_to_u8_rgba(color)
         }
     }
+
+    /// Most cells any liquid or gas spreads per move step, i.e. the number of dispersion passes
+    /// needed
+    pub fn max_dispersion(&self) -> u32 {
+        self.iter()
+            .filter(|d| {
+                d.behaviour.contains(&MatterBehaviour::Liquid)
+                    || d.behaviour.contains(&MatterBehaviour::Gas)
+            })
+            .map(|d| d.dispersion)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Matter with its default color & initial state
+    pub fn matter_with_color(&self, id: MatterId) -> MatterWithColor {
+        let color = self.color_rgba_u8(id);
+        self.with_initial_state(MatterWithColor::with_color(id, [
+            color[0], color[1], color[2],
+        ]))
+    }
+
+    /// Matter with the initial state of its definition, like when drawn
+    pub fn with_initial_state(&self, matter: MatterWithColor) -> MatterWithColor {
+        let lifetime = self
+            .get(matter.matter_id())
+            .map(|d| d.lifetime)
+            .unwrap_or(0);
+        MatterWithColor {
+            state: lifetime,
+            ..matter
+        }
+    }
+
+    /// Both sides of every reaction, indexed by matter id pairs
+    fn reaction_table(&self) -> io::Result<Vec<ReactionGpu>> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut reactions = vec![ReactionGpu::default(); 256 * 256];
+        for definition in self.iter() {
+            for reaction in &definition.reactions {
+                if !(reaction.probability > 0.0 && reaction.probability <= 1.0) {
+                    return Err(invalid(format!(
+                        "Probability of {} reacting with {} is not within 0-1",
+                        definition.name, reaction.with
+                    )));
+                }
+                let a = definition.id as usize;
+                let b = self.find(&reaction.with).unwrap().0 as usize;
+                let a_into = self.named_matter(Some(&reaction.into)).value;
+                let b_into = self.named_matter(Some(&reaction.other_into)).value;
+                if reactions[a * 256 + b].probability > 0.0 || (a == b && a_into != b_into) {
+                    return Err(invalid(format!(
+                        "{} reacts with {} more than one way",
+                        definition.name, reaction.with
+                    )));
+                }
+                reactions[a * 256 + b] = ReactionGpu {
+                    probability: reaction.probability,
+                    into: a_into,
+                };
+                reactions[b * 256 + a] = ReactionGpu {
+                    probability: reaction.probability,
+                    into: b_into,
+                };
+            }
+        }
+        Ok(reactions)
+    }
+
+    /// What matter `a` turns into when touching matter `b`
+    #[cfg(test)]
+    pub fn reaction(&self, a: MatterId, b: MatterId) -> ReactionGpu {
+        self.reactions[a.0 as usize * 256 + b.0 as usize]
+    }
+
+    /// Reaction table for the kernels, see [`MatterRegistry::reaction`]
+    pub fn gpu_reactions(&self) -> &[ReactionGpu] {
+        &self.reactions
+    }
+
+    /// Matter left behind by burning matter, with its initial state
+    pub fn burns_into(&self, id: MatterId) -> MatterWithColor {
+        self.named_matter(self.get(id).and_then(|d| d.burns_into.as_ref()))
+    }
+
+    /// Matter by name with its color & initial state, empty if not found
+    pub fn named_matter(&self, name: Option<&String>) -> MatterWithColor {
+        let id = name
+            .and_then(|name| self.find(name))
+            .unwrap_or(MatterId::EMPTY);
+        self.matter_with_color(id)
+    }
+
+    /// Definitions of all 256 ids for the kernels, unknown ids have no behaviour
+    pub fn gpu_definitions(&self) -> Vec<MatterDefinitionGpu> {
+        self.definitions
+            .iter()
+            .map(|d| {
+                d.as_ref()
+                    .map(|d| MatterDefinitionGpu {
+                        flags: d.flags(),
+                        color_variation: d.color_variation,
+                        dispersion: d.dispersion,
+                        lifetime: d.lifetime,
+                        density: d.density,
+                        friction: d.friction,
+                        flammability: d.flammability,
+                        burns_into: self.burns_into(d.matter_id()).value,
+                        conductivity: d.conductivity,
+                        is_heat_source: d.temperature.is_some() as u32,
+                        temperature: d.temperature.unwrap_or(0.0),
+                        above_temperature: d
+                            .above
+                            .as_ref()
+                            .map(|t| t.temperature)
+                            .unwrap_or(f32::INFINITY),
+                        above_into: self.named_matter(d.above.as_ref().map(|t| &t.into)).value,
+                        below_temperature: d
+                            .below
+                            .as_ref()
+                            .map(|t| t.temperature)
+                            .unwrap_or(f32::NEG_INFINITY),
+                        below_into: self.named_matter(d.below.as_ref().map(|t| &t.into)).value,
+                    })
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
 }
 
-/// Matter data where first 3 bytes are saved for color and last 4th byte is saved for matter id
-#[derive(Default, Copy, Clone)]
+/// Bits of [`MatterWithColor::state`] holding the remaining lifetime of gas
+pub const LIFETIME_MASK: u32 = 255;
+/// [`MatterWithColor::state`] bits of the fall velocity of falling matter, see `fall_empty.glsl`
+#[cfg(test)]
+pub const VELOCITY_SHIFT: u32 = 8;
+#[cfg(test)]
+pub const VELOCITY_MASK: u32 = 255;
+
+/// Matter data where first 3 bytes are saved for color and last 4th byte is saved for matter id.
+/// Same layout as a cell in the simulation buffers, see `matter.glsl`.
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Zeroable, Pod)]
 pub struct MatterWithColor {
     pub value: u32,
+    /// Per cell state, bits 0-7: remaining lifetime, bits 8-15: fall velocity
+    pub state: u32,
 }
 
 impl MatterWithColor {
-    /// Creates a new matter with color from matter id giving it a slightly randomized color
-    pub fn new(matter_id: MatterId) -> MatterWithColor {
-        let color = matter_id.color_rgba_u8();
+    /// Creates a new matter with given color
+    pub fn with_color(matter_id: MatterId, rgb: [u8; 3]) -> MatterWithColor {
         MatterWithColor {
-            value: u8_rgba_to_u32_rgba(color[0], color[1], color[2], matter_id as u8),
+            value: u8_rgba_to_u32_rgba(rgb[0], rgb[1], rgb[2], matter_id.0),
+            state: 0,
         }
     }
 
     pub fn matter_id(&self) -> MatterId {
-        ((self.value & 255) as u8).into()
+        MatterId((self.value & 255) as u8)
+    }
+
+    #[cfg(test)]
+    pub fn lifetime(&self) -> u32 {
+        self.state & LIFETIME_MASK
+    }
+
+    /// Fall velocity in 1/16 cells per fall pass
+    #[cfg(test)]
+    pub fn velocity(&self) -> u32 {
+        (self.state >> VELOCITY_SHIFT) & VELOCITY_MASK
     }
 }
 
@@ -65,6 +498,196 @@ impl From<u32> for MatterWithColor {
     fn from(item: u32) -> Self {
         Self {
             value: item,
+            state: 0,
         }
     }
 }
+
+/// Id of matter in the default matter definitions
+#[cfg(test)]
+pub fn test_matter(name: &str) -> MatterId {
+    MatterRegistry::default().find(name).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::matter::{MatterBehaviour, MatterId, MatterRegistry};
+
+    #[test]
+    fn test_default_registry() {
+        let registry = MatterRegistry::default();
+        let sand = registry.find("Sand").unwrap();
+        assert_eq!(registry.find("Empty"), Some(MatterId::EMPTY));
+        assert!(registry.has(sand, MatterBehaviour::Gravity));
+        assert!(!registry.has(MatterId::EMPTY, MatterBehaviour::Gravity));
+        assert_eq!(registry.matter_with_color(sand).matter_id(), sand);
+        let gpu = registry.gpu_definitions();
+        assert_eq!(gpu.len(), 256);
+        assert_eq!(
+            gpu[sand.0 as usize].flags,
+            registry.get(sand).unwrap().flags()
+        );
+    }
+
+    #[test]
+    fn test_registry_from_ron() {
+        let registry = MatterRegistry::from_ron(
+            r#"[
+                (name: "Empty", id: 0, color: 0x0),
+                (name: "Water", id: 7, color: 0x1ca3ec, behaviour: [Gravity, Liquid]),
+            ]"#,
+        )
+        .unwrap();
+        let water = registry.find("Water").unwrap();
+        assert_eq!(water, MatterId(7));
+        assert_eq!(registry.get(water).unwrap().flags(), 0b1001);
+        assert_eq!(registry.iter().count(), 2);
+        assert_eq!(registry.name(MatterId(3)), "Unknown(3)");
+
+        // Empty must exist
+        assert!(MatterRegistry::from_ron(r#"[(name: "Sand", id: 0, color: 0x0)]"#).is_err());
+        // Ids must be unique
+        assert!(MatterRegistry::from_ron(
+            r#"[(name: "Empty", id: 0, color: 0x0), (name: "Sand", id: 0, color: 0x0)]"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_registry_default_brush() {
+        let registry = MatterRegistry::default();
+        assert_eq!(registry.default_brush(), registry.find("Sand").unwrap());
+        let registry = MatterRegistry::from_ron(
+            r#"[(name: "Empty", id: 0, color: 0x0), (name: "Snow", id: 5, color: 0x0)]"#,
+        )
+        .unwrap();
+        assert_eq!(registry.default_brush(), MatterId(5));
+        let registry = MatterRegistry::from_ron(r#"[(name: "Empty", id: 0, color: 0x0)]"#).unwrap();
+        assert_eq!(registry.default_brush(), MatterId::EMPTY);
+    }
+
+    #[test]
+    fn test_registry_transitions() {
+        // Transitions are uploaded as packed matter, missing ones never happen
+        let registry = MatterRegistry::from_ron(
+            r#"[
+                (name: "Empty", id: 0, color: 0x0),
+                (name: "Ice", id: 1, color: 0x0, above: (temperature: 0.0, into: "Water")),
+                (name: "Water", id: 2, color: 0x0, below: (temperature: 0.0, into: "Ice")),
+            ]"#,
+        )
+        .unwrap();
+        let gpu = registry.gpu_definitions();
+        assert_eq!(
+            gpu[1].above_into,
+            registry.matter_with_color(MatterId(2)).value
+        );
+        assert_eq!(gpu[1].below_temperature, f32::NEG_INFINITY);
+        assert_eq!(
+            gpu[2].below_into,
+            registry.matter_with_color(MatterId(1)).value
+        );
+        assert_eq!(gpu[2].above_temperature, f32::INFINITY);
+
+        // Transitions must turn into known matter
+        assert!(MatterRegistry::from_ron(
+            r#"[(name: "Empty", id: 0, color: 0x0), (name: "Ice", id: 1, color: 0x0, above: (temperature: 0.0, into: "Water"))]"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_registry_reactions() {
+        // Reactions are stored for both sides
+        let registry = MatterRegistry::default();
+        let (lava, water) = (
+            registry.find("Lava").unwrap(),
+            registry.find("Water").unwrap(),
+        );
+        let (stone, steam) = (
+            registry.find("Stone").unwrap(),
+            registry.find("Steam").unwrap(),
+        );
+        assert_eq!(
+            registry.reaction(lava, water).into,
+            registry.matter_with_color(stone).value
+        );
+        assert_eq!(
+            registry.reaction(water, lava).into,
+            registry.matter_with_color(steam).value
+        );
+        assert_eq!(registry.reaction(water, stone).probability, 0.0);
+    }
+
+    #[test]
+    fn test_registry_invalid_reactions() {
+        // Each pair only reacts one way
+        assert!(MatterRegistry::from_ron(
+            r#"[
+                (name: "Empty", id: 0, color: 0x0),
+                (name: "A", id: 1, color: 0x0,
+                    reactions: [(with: "B", into: "B", other_into: "A", probability: 0.5)]),
+                (name: "B", id: 2, color: 0x0,
+                    reactions: [(with: "A", into: "Empty", other_into: "Empty", probability: 0.5)]),
+            ]"#
+        )
+        .is_err());
+        // Reactions must be with & into known matter
+        assert!(MatterRegistry::from_ron(
+            r#"[
+                (name: "Empty", id: 0, color: 0x0),
+                (name: "A", id: 1, color: 0x0,
+                    reactions: [(with: "B", into: "Empty", other_into: "Empty", probability: 0.5)]),
+            ]"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_registry_burns_into() {
+        let registry = MatterRegistry::from_ron(
+            r#"[
+                (name: "Empty", id: 0, color: 0x0),
+                (name: "Ash", id: 1, color: 0x0),
+                (name: "Wood", id: 2, color: 0x0, burns_into: "Ash"),
+                (name: "Oil", id: 3, color: 0x0),
+            ]"#,
+        )
+        .unwrap();
+        let gpu = registry.gpu_definitions();
+        assert_eq!(
+            gpu[2].burns_into,
+            registry.matter_with_color(MatterId(1)).value
+        );
+        // Burns away if not given
+        assert_eq!(
+            gpu[3].burns_into,
+            registry.matter_with_color(MatterId::EMPTY).value
+        );
+
+        // Burning matter must burn into known matter
+        assert!(MatterRegistry::from_ron(
+            r#"[(name: "Empty", id: 0, color: 0x0), (name: "Fire", id: 1, color: 0x0, burns_into: "Ash")]"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_registry_friction() {
+        let registry = MatterRegistry::from_ron(
+            r#"[(name: "Empty", id: 0, color: 0x0), (name: "Snow", id: 1, color: 0x0, friction: 0.5)]"#,
+        )
+        .unwrap();
+        assert_eq!(registry.gpu_definitions()[1].friction, 0.5);
+
+        // Friction is a chance
+        assert!(MatterRegistry::from_ron(
+            r#"[(name: "Empty", id: 0, color: 0x0), (name: "Snow", id: 1, color: 0x0, friction: 1.5)]"#
+        )
+        .is_err());
+        assert!(MatterRegistry::from_ron(
+            r#"[(name: "Empty", id: 0, color: 0x0), (name: "Snow", id: 1, color: 0x0, friction: -0.5)]"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use vulkano::{device::Device, pipeline::cache::PipelineCache};
+
+/// Vulkan pipeline cache kept on disk between runs, so pipelines are compiled only on the first run with
+/// a device & driver
+pub struct PipelineCacheFile {
+    pub cache: Arc<PipelineCache>,
+    path: PathBuf,
+}
+
+impl PipelineCacheFile {
+    /// Load the cache of `device` from `dir`. Starts empty if there is no cache for the device & driver
+    /// yet, or if it can't be read. The cache is only an optimization, errors are just logged.
+    pub fn load(device: Arc<Device>, dir: impl AsRef<Path>) -> PipelineCacheFile {
+        let path = dir
+            .as_ref()
+            .join(format!("{}.bin", pipeline_cache_key(&device)));
+        let cache = match fs::read(&path) {
+            // Safety: the file is keyed by device & driver, and drivers check the header of the data
+            // themselves, ignoring data of other devices or driver versions
+            Ok(data) => unsafe { PipelineCache::with_data(device.clone(), &data) }
+                .map_err(|e| {
+                    bevy::log::error!("Failed to load pipeline cache {:?}: {}", path, e);
+                })
+                .ok(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                bevy::log::error!("Failed to read pipeline cache {:?}: {}", path, e);
+                None
+            }
+        };
+        PipelineCacheFile {
+            cache: cache.unwrap_or_else(|| PipelineCache::empty(device).unwrap()),
+            path,
+        }
+    }
+
+    /// Save the cache, including pipelines created since loading it
+    pub fn save(&self) -> io::Result<()> {
+        let data = self
+            .cache
+            .get_data()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Write then rename, so an interrupted save never leaves a partial cache to be loaded
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, data)?;
+        fs::rename(tmp_path, &self.path)
+    }
+}
+
+/// Device & driver UUIDs in hex. Falls back to the pipeline cache UUID, which changes with the driver,
+/// on devices that don't report them.
+fn pipeline_cache_key(device: &Device) -> String {
+    let properties = device.physical_device().properties();
+    let device_uuid = properties
+        .device_uuid
+        .unwrap_or(properties.pipeline_cache_uuid);
+    let driver_uuid = properties
+        .driver_uuid
+        .unwrap_or(properties.pipeline_cache_uuid);
+    format!("{}-{}", hex(&device_uuid), hex(&driver_uuid))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use vulkano_util::context::VulkanoContext;
+
+    use crate::{
+        ca_simulator::{CASimulator, CASimulatorConfig},
+        matter::MatterRegistry,
+        pipeline_cache::PipelineCacheFile,
+    };
+
+    #[test]
+    fn test_pipeline_cache_round_trip() {
+        let context = VulkanoContext::default();
+        let dir = std::env::temp_dir().join("cellular_automata_test_pipeline_cache");
+        let _ = fs::remove_dir_all(&dir);
+
+        let pipeline_cache = PipelineCacheFile::load(context.device().clone(), &dir);
+        let _simulator = CASimulator::new(
+            context.compute_queue(),
+            CASimulatorConfig::default(),
+            MatterRegistry::default(),
+            Some(pipeline_cache.cache.clone()),
+        );
+        pipeline_cache.save().unwrap();
+        let saved = pipeline_cache.cache.get_data().unwrap();
+        assert!(!saved.is_empty());
+
+        // Loading again gets the pipelines of the last run
+        let loaded = PipelineCacheFile::load(context.device().clone(), &dir);
+        assert_eq!(loaded.path, pipeline_cache.path);
+        assert!(loaded.cache.get_data().unwrap().len() >= saved.len());
+
+        // A corrupted cache is ignored
+        fs::write(&loaded.path, b"not a pipeline cache").unwrap();
+        let corrupted = PipelineCacheFile::load(context.device().clone(), &dir);
+        assert!(corrupted.cache.get_data().is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
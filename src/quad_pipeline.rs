@@ -9,6 +9,7 @@ use vulkano::{
     device::Queue,
     image::{ImageAccess, ImageViewAbstract},
     pipeline::{
+        cache::PipelineCache,
         graphics::{
             color_blend::ColorBlendState,
             input_assembly::InputAssemblyState,
@@ -35,21 +36,27 @@ pub struct DrawQuadPipeline {
 }
 
 impl DrawQuadPipeline {
-    pub fn new(gfx_queue: Arc<Queue>, subpass: Subpass) -> DrawQuadPipeline {
+    pub fn new(
+        gfx_queue: Arc<Queue>,
+        subpass: Subpass,
+        pipeline_cache: Option<Arc<PipelineCache>>,
+    ) -> DrawQuadPipeline {
         let quad = TexturedQuad::new(1.0, 1.0, [1.0; 4]).to_mesh(gfx_queue.device().clone());
         let pipeline = {
             let vs = vs::load(gfx_queue.device().clone()).expect("failed to create shader module");
             let fs = fs::load(gfx_queue.device().clone()).expect("failed to create shader module");
-            GraphicsPipeline::start()
+            let mut builder = GraphicsPipeline::start()
                 .vertex_input_state(BuffersDefinition::new().vertex::<TexturedVertex>())
                 .vertex_shader(vs.entry_point("main").unwrap(), ())
                 .input_assembly_state(InputAssemblyState::new())
                 .fragment_shader(fs.entry_point("main").unwrap(), ())
                 .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
                 .render_pass(subpass.clone())
-                .color_blend_state(ColorBlendState::default().blend_alpha())
-                .build(gfx_queue.device().clone())
-                .unwrap()
+                .color_blend_state(ColorBlendState::default().blend_alpha());
+            if let Some(pipeline_cache) = pipeline_cache {
+                builder = builder.build_with_cache(pipeline_cache);
+            }
+            builder.build(gfx_queue.device().clone()).unwrap()
         };
         DrawQuadPipeline {
             gfx_queue,
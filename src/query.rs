@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use bevy::math::IVec2;
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer, TypedBufferAccess},
+    device::Device,
+};
+
+use crate::matter::MatterWithColor;
+
+/// Result of an asynchronous matter query, `None` if the position was outside the canvas
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct QueryResult {
+    pub pos: IVec2,
+    pub matter: Option<MatterWithColor>,
+}
+
+/// The readback buffer of submitted queries & the queries copied into it
+pub struct QueryReadback {
+    pub buffer: Arc<CpuAccessibleBuffer<[MatterWithColor]>>,
+    /// Positions in the order their cells are copied into the buffer
+    pub positions: Vec<IVec2>,
+    /// Have the cells been copied into the buffer? Submitted queries wait for the next step to copy them.
+    pub is_copied: bool,
+}
+
+impl QueryReadback {
+    pub fn new(device: Arc<Device>, capacity: usize) -> QueryReadback {
+        QueryReadback {
+            buffer: readback_buffer(device, capacity),
+            positions: vec![],
+            is_copied: false,
+        }
+    }
+
+    /// Make sure the buffer fits `len` queries
+    pub fn reserve(&mut self, device: Arc<Device>, len: usize) {
+        if self.buffer.len() < len as u64 {
+            self.buffer = readback_buffer(device, len.next_power_of_two());
+        }
+    }
+
+    /// Are there queries waiting for their cells to be copied?
+    pub fn is_pending(&self) -> bool {
+        !self.positions.is_empty() && !self.is_copied
+    }
+
+    /// Has the copy into the buffer finished?
+    pub fn is_ready(&self) -> bool {
+        self.is_copied
+    }
+
+    /// Take the copied results & free the buffer for the next queries
+    pub fn take_results(&mut self) -> Vec<QueryResult> {
+        self.is_copied = false;
+        let cells = self.buffer.read().unwrap();
+        self.positions
+            .drain(..)
+            .zip(cells.iter())
+            .map(|(pos, &matter)| QueryResult {
+                pos,
+                matter: Some(matter),
+            })
+            .collect()
+    }
+}
+
+fn readback_buffer(device: Arc<Device>, len: usize) -> Arc<CpuAccessibleBuffer<[MatterWithColor]>> {
+    CpuAccessibleBuffer::from_iter(
+        device,
+        BufferUsage::transfer_dst(),
+        true,
+        (0..len).map(|_| MatterWithColor::default()),
+    )
+    .unwrap()
+}
@@ -7,6 +7,7 @@ use vulkano::{
     device::Queue,
     format::Format,
     image::ImageAccess,
+    pipeline::cache::PipelineCache,
     render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
     sync::GpuFuture,
 };
@@ -22,7 +23,11 @@ pub struct FillScreenRenderPass {
 }
 
 impl FillScreenRenderPass {
-    pub fn new(gfx_queue: Arc<Queue>, output_format: Format) -> FillScreenRenderPass {
+    pub fn new(
+        gfx_queue: Arc<Queue>,
+        output_format: Format,
+        pipeline_cache: Option<Arc<PipelineCache>>,
+    ) -> FillScreenRenderPass {
         let render_pass = vulkano::single_pass_renderpass!(gfx_queue.device().clone(),
             attachments: {
                 color: {
@@ -40,7 +45,7 @@ impl FillScreenRenderPass {
         )
         .unwrap();
         let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
-        let quad_pipeline = DrawQuadPipeline::new(gfx_queue.clone(), subpass);
+        let quad_pipeline = DrawQuadPipeline::new(gfx_queue.clone(), subpass, pipeline_cache);
         FillScreenRenderPass {
             gfx_queue,
             render_pass,
@@ -50,6 +55,7 @@ impl FillScreenRenderPass {
 
     /// Place view exactly over swapchain image target.
     /// Texture draw pipeline uses a quad onto which it places the view.
+    #[allow(clippy::too_many_arguments)]
     pub fn draw<F>(
         &mut self,
         before_future: F,
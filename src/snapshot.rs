@@ -0,0 +1,324 @@
+use std::io::{self, Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::matter::{MatterRegistry, MatterWithColor};
+
+/// Magic bytes at the start of every snapshot file
+const SNAPSHOT_MAGIC: [u8; 4] = *b"CASN";
+/// Version of the snapshot file format, files of other versions aren't read
+const SNAPSHOT_VERSION: u32 = 1;
+/// Most cells a snapshot may have, larger sizes in a header are treated as invalid
+const MAX_SNAPSHOT_CELLS: u32 = 1 << 26;
+/// Bytes per cell in the compressed payload: value, state & temperature
+const CELL_BYTES: usize = 12;
+
+/// A saved simulation state.
+///
+/// File format (little endian):
+/// - magic `CASN`, version: u32
+/// - width: u32, height: u32
+/// - matter table: count: u32, then for each matter: id: u8, name length: u8, name as utf-8
+/// - sim_step: u32, move_step: u32, world_seed: u32
+/// - zlib compressed cells, width * height [`MatterWithColor`]s as value: u32, state: u32, followed by
+///   the width * height cell temperatures as f32 in the same stream
+///
+/// The matter table maps the ids used in the file to matter names, so files stay loadable after
+/// matter ids change or new matter is added.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub width: u32,
+    pub height: u32,
+    pub sim_step: u32,
+    pub move_step: u32,
+    pub world_seed: u32,
+    pub cells: Vec<MatterWithColor>,
+    pub temperature: Vec<f32>,
+}
+
+impl Snapshot {
+    /// Write snapshot using the matter ids of the registry
+    pub fn write(&self, writer: impl Write, matter_registry: &MatterRegistry) -> io::Result<()> {
+        let matter_table = matter_registry
+            .iter()
+            .map(|matter| (matter.id, matter.name.clone()))
+            .collect::<Vec<_>>();
+        self.write_with_matter_table(writer, &matter_table)
+    }
+
+    fn write_with_matter_table(
+        &self,
+        mut writer: impl Write,
+        matter_table: &[(u8, String)],
+    ) -> io::Result<()> {
+        assert_eq!(self.cells.len(), (self.width * self.height) as usize);
+        assert_eq!(self.temperature.len(), self.cells.len());
+        writer.write_all(&SNAPSHOT_MAGIC)?;
+        writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        writer.write_all(&self.width.to_le_bytes())?;
+        writer.write_all(&self.height.to_le_bytes())?;
+        writer.write_all(&(matter_table.len() as u32).to_le_bytes())?;
+        for (id, name) in matter_table {
+            writer.write_all(&[*id, name.len() as u8])?;
+            writer.write_all(name.as_bytes())?;
+        }
+        writer.write_all(&self.sim_step.to_le_bytes())?;
+        writer.write_all(&self.move_step.to_le_bytes())?;
+        writer.write_all(&self.world_seed.to_le_bytes())?;
+        let mut encoder = ZlibEncoder::new(writer, Compression::default());
+        for cell in &self.cells {
+            encoder.write_all(&cell.value.to_le_bytes())?;
+            encoder.write_all(&cell.state.to_le_bytes())?;
+        }
+        for temperature in &self.temperature {
+            encoder.write_all(&temperature.to_le_bytes())?;
+        }
+        encoder.finish()?;
+        Ok(())
+    }
+
+    /// Read snapshot, mapping the matter ids of the file to the registry's matter ids by name
+    pub fn read(mut reader: impl Read, matter_registry: &MatterRegistry) -> io::Result<Snapshot> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != SNAPSHOT_MAGIC {
+            return Err(invalid_data("Not a snapshot file".to_string()));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported snapshot version {}",
+                version
+            )));
+        }
+        let width = read_u32(&mut reader)?;
+        let height = read_u32(&mut reader)?;
+        let num_cells = width
+            .checked_mul(height)
+            .filter(|&num_cells| num_cells <= MAX_SNAPSHOT_CELLS)
+            .ok_or_else(|| invalid_data(format!("Invalid snapshot size ({},{})", width, height)))?;
+
+        // File matter id -> current matter id
+        let mut matter_ids = [None; 256];
+        for _ in 0..read_u32(&mut reader)? {
+            let mut id_and_len = [0; 2];
+            reader.read_exact(&mut id_and_len)?;
+            let mut name = vec![0; id_and_len[1] as usize];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name)
+                .map_err(|_| invalid_data("Invalid matter name".to_string()))?;
+            let matter = matter_registry
+                .find(&name)
+                .ok_or_else(|| invalid_data(format!("Unknown matter {}", name)))?;
+            matter_ids[id_and_len[0] as usize] = Some(matter);
+        }
+        let sim_step = read_u32(&mut reader)?;
+        let move_step = read_u32(&mut reader)?;
+        let world_seed = read_u32(&mut reader)?;
+
+        // The payload only grows with the data that's actually there, a header can't make us allocate
+        // for cells the file doesn't have
+        let num_cells = num_cells as usize;
+        let payload_len = num_cells * CELL_BYTES;
+        let mut decoder = ZlibDecoder::new(reader);
+        let mut payload = vec![];
+        (&mut decoder)
+            .take(payload_len as u64)
+            .read_to_end(&mut payload)?;
+        if payload.len() != payload_len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Snapshot has fewer cells than its size",
+            ));
+        }
+        if decoder.read(&mut [0])? != 0 {
+            return Err(invalid_data(
+                "Snapshot has more cells than its size".to_string(),
+            ));
+        }
+
+        let (cell_bytes, temperature_bytes) = payload.split_at(num_cells * 8);
+        let cells = cell_bytes
+            .chunks_exact(8)
+            .map(|bytes| {
+                let value = u32_from_le_bytes(&bytes[0..4]);
+                let matter = matter_ids[(value & 255) as usize].ok_or_else(|| {
+                    invalid_data(format!("Matter id {} not in table", value & 255))
+                })?;
+                Ok(MatterWithColor {
+                    value: (value & !255) | matter.0 as u32,
+                    state: u32_from_le_bytes(&bytes[4..8]),
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let temperature = temperature_bytes
+            .chunks_exact(4)
+            .map(|bytes| f32::from_bits(u32_from_le_bytes(bytes)))
+            .collect();
+        Ok(Snapshot {
+            width,
+            height,
+            sim_step,
+            move_step,
+            world_seed,
+            cells,
+            temperature,
+        })
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn u32_from_le_bytes(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().unwrap())
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{
+        matter::{MatterId, MatterRegistry, MatterWithColor},
+        snapshot::Snapshot,
+    };
+
+    fn test_snapshot() -> Snapshot {
+        let registry = MatterRegistry::default();
+        let (width, height) = (7, 5);
+        Snapshot {
+            width,
+            height,
+            sim_step: 42,
+            move_step: 84,
+            world_seed: 0xdeadbeef,
+            cells: (0..width * height)
+                .map(|i| match i % 4 {
+                    0 => registry.matter_with_color(MatterId::EMPTY),
+                    1 => registry.matter_with_color(registry.find("Sand").unwrap()),
+                    2 => registry.matter_with_color(registry.find("Wood").unwrap()),
+                    _ => MatterWithColor {
+                        state: i,
+                        ..registry.matter_with_color(registry.find("Smoke").unwrap())
+                    },
+                })
+                .collect(),
+            temperature: (0..width * height)
+                .map(|i| i as f32 * 12.5 - 40.0)
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let snapshot = test_snapshot();
+        let mut bytes = vec![];
+        snapshot
+            .write(&mut bytes, &MatterRegistry::default())
+            .unwrap();
+        assert_eq!(&bytes[0..4], b"CASN");
+        assert_eq!(
+            Snapshot::read(&bytes[..], &MatterRegistry::default()).unwrap(),
+            snapshot
+        );
+    }
+
+    #[test]
+    fn test_snapshot_remaps_matter_ids() {
+        // A file written when Sand & Wood had different ids
+        let snapshot = test_snapshot();
+        let registry = MatterRegistry::default();
+        let old_ids = |matter: MatterId| match registry.name(matter).as_str() {
+            "Sand" => 7,
+            "Wood" => 3,
+            "Smoke" => 9,
+            _ => matter.0,
+        };
+        let old_snapshot = Snapshot {
+            cells: snapshot
+                .cells
+                .iter()
+                .map(|m| MatterWithColor {
+                    value: (m.value & !255) | old_ids(m.matter_id()) as u32,
+                    ..*m
+                })
+                .collect(),
+            ..snapshot.clone()
+        };
+        let mut bytes = vec![];
+        old_snapshot
+            .write_with_matter_table(&mut bytes, &[
+                (0, "Empty".to_string()),
+                (7, "Sand".to_string()),
+                (3, "Wood".to_string()),
+                (9, "Smoke".to_string()),
+            ])
+            .unwrap();
+        assert_eq!(
+            Snapshot::read(&bytes[..], &MatterRegistry::default()).unwrap(),
+            snapshot
+        );
+    }
+
+    #[test]
+    fn test_snapshot_other_version() {
+        let mut bytes = vec![];
+        test_snapshot()
+            .write(&mut bytes, &MatterRegistry::default())
+            .unwrap();
+        bytes[4..8].copy_from_slice(&2u32.to_le_bytes());
+        assert_eq!(
+            Snapshot::read(&bytes[..], &MatterRegistry::default())
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_snapshot_invalid() {
+        let mut bytes = vec![];
+        test_snapshot()
+            .write_with_matter_table(&mut bytes, &[
+                (0, "Empty".to_string()),
+                (1, "Unobtainium".to_string()),
+            ])
+            .unwrap();
+        assert!(Snapshot::read(&bytes[..], &MatterRegistry::default()).is_err());
+        assert!(Snapshot::read(&b"PNG\0"[..], &MatterRegistry::default()).is_err());
+        // Truncated
+        let mut bytes = vec![];
+        test_snapshot()
+            .write(&mut bytes, &MatterRegistry::default())
+            .unwrap();
+        assert!(Snapshot::read(&bytes[..bytes.len() - 8], &MatterRegistry::default()).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_invalid_size() {
+        let mut bytes = vec![];
+        test_snapshot()
+            .write(&mut bytes, &MatterRegistry::default())
+            .unwrap();
+        let with_size = |width: u32, height: u32| {
+            let mut bytes = bytes.clone();
+            bytes[8..12].copy_from_slice(&width.to_le_bytes());
+            bytes[12..16].copy_from_slice(&height.to_le_bytes());
+            Snapshot::read(&bytes[..], &MatterRegistry::default())
+                .unwrap_err()
+                .kind()
+        };
+        // Overflowing, too large & not matching the cells
+        assert_eq!(with_size(u32::MAX, u32::MAX), io::ErrorKind::InvalidData);
+        assert_eq!(with_size(1 << 16, 1 << 16), io::ErrorKind::InvalidData);
+        assert_eq!(with_size(5, 5), io::ErrorKind::InvalidData);
+        assert_eq!(with_size(7, 6), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -12,15 +12,13 @@ use vulkano::{
     device::{Device, Queue},
     image::ImageViewAbstract,
     pipeline::{
-        layout::PipelineLayoutCreateInfo, ComputePipeline, GraphicsPipeline, Pipeline,
-        PipelineLayout,
+        cache::PipelineCache, layout::PipelineLayoutCreateInfo, ComputePipeline, GraphicsPipeline,
+        Pipeline, PipelineLayout,
     },
     sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode},
     shader::{EntryPoint, ShaderStages, SpecializationConstants},
 };
 
-use crate::{CANVAS_SIZE_X, CANVAS_SIZE_Y};
-
 /// Descriptor set layout binding information for storage buffer
 pub fn storage_buffer_desc() -> DescriptorSetLayoutBinding {
     DescriptorSetLayoutBinding {
@@ -37,7 +35,8 @@ pub fn storage_image_desc() -> DescriptorSetLayoutBinding {
     }
 }
 
-/// Creates a compute pipeline from given shader, with given descriptor layout binding.
+/// Creates a compute pipeline from given shader, with given descriptor layout binding, using the pipeline cache
+/// if given.
 /// The intention here is that the descriptor layout should correspond the shader's layout.
 /// Normally you would use `ComputePipeline::new`, which would generate layout for descriptor set
 /// automatically. However, because I've split the shaders to various different shaders, each shader
@@ -48,6 +47,7 @@ pub fn create_compute_pipeline<Css>(
     shader_entry_point: EntryPoint,
     descriptor_layout: Vec<(u32, DescriptorSetLayoutBinding)>,
     specialization_constants: &Css,
+    pipeline_cache: Option<Arc<PipelineCache>>,
 ) -> Arc<ComputePipeline>
 where
     Css: SpecializationConstants,
@@ -77,7 +77,7 @@ where
         shader_entry_point,
         specialization_constants,
         pipeline_layout.clone(),
-        None,
+        pipeline_cache,
     )
     .unwrap()
 }
@@ -89,7 +89,7 @@ pub fn create_image_sampler_nearest_descriptor_set(
     pipeline: Arc<GraphicsPipeline>,
     image: Arc<dyn ImageViewAbstract>,
 ) -> Arc<PersistentDescriptorSet> {
-    let layout = pipeline.layout().set_layouts().get(0).unwrap();
+    let layout = pipeline.layout().set_layouts().first().unwrap();
     let sampler = Sampler::new(device, SamplerCreateInfo {
         mag_filter: Filter::Nearest,
         min_filter: Filter::Nearest,
@@ -151,9 +151,9 @@ impl MousePos {
         }
     }
 
-    /// Converts world position to canvas position:
+    /// Converts world position to the position on a canvas of `canvas_size`:
     /// Inverts y and adds half canvas to the position (pixel units)
-    pub fn canvas_pos(&self) -> Vec2 {
-        self.world + Vec2::new(CANVAS_SIZE_X as f32 / 2.0, CANVAS_SIZE_Y as f32 / 2.0)
+    pub fn canvas_pos(&self, canvas_size: UVec2) -> Vec2 {
+        self.world + canvas_size.as_vec2() / 2.0
     }
 }
@@ -67,14 +67,14 @@ impl TexturedQuad {
                 device.clone(),
                 BufferUsage::vertex_buffer(),
                 false,
-                self.vertices.into_iter(),
+                self.vertices,
             )
             .unwrap(),
             indices: CpuAccessibleBuffer::<[u32]>::from_iter(
                 device.clone(),
                 BufferUsage::index_buffer(),
                 false,
-                self.indices.into_iter(),
+                self.indices,
             )
             .unwrap(),
         }